@@ -0,0 +1,857 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cache::Cache;
+use crate::citations::{self, Citation, SourceRange};
+use crate::documents;
+use crate::llm_api::LLMApi;
+use crate::llm_prompt::Prompt;
+use crate::project_index::{ProjectIndex, Retrieval};
+use crate::query_rewrite::{self, Rewrite};
+use crate::rerank::{self, Rerank};
+use crate::symbol_index::Relation;
+use crate::utils::estimate_tokens;
+use crate::vector_utils::reciprocal_rank_fusion;
+use crate::{read_question, VERBOSE};
+
+const ASK_ANSWER_PROMPT: &str = "Use the code above to answer the following question:";
+// Earlier questions and answers are added to the answer prompt up to this size
+const HISTORY_TOKEN_BUDGET: usize = 2000;
+
+// Exit codes of "ask", scripts may rely on them. 1 is any other error.
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NO_FILES: i32 = 3;
+pub const EXIT_LLM: i32 = 4;
+// No index, or built with another embedding model
+pub const EXIT_INDEX: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unsupported output format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AskError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl AskError {
+    pub fn new(code: i32, message: String) -> Self {
+        AskError { code, message }
+    }
+}
+
+pub struct AskOptions {
+    pub retrieval: Retrieval,
+    pub top_k: usize,
+    pub format: OutputFormat,
+    pub rerank: Option<Rerank>,
+    // Budget for definitions related to the retrieved files, 0 disables the expansion
+    pub context_tokens: usize,
+    // Only search this root or folder, see parse_scope
+    pub scope: Option<String>,
+    // false leaves the document chunks of "index --docs" out of retrieval
+    pub docs: bool,
+    // Also search with an LLM rewrite of the question
+    pub rewrite: Option<Rewrite>,
+}
+
+// The question of a non-interactive run: --question, --question-file or a stdin
+// pipe. None means the interactive session.
+pub fn read_question_arg(
+    question: Option<&String>,
+    question_file: Option<&String>,
+) -> Result<Option<String>, AskError> {
+    let question = if let Some(question) = question {
+        question.clone()
+    } else if let Some(file) = question_file {
+        std::fs::read_to_string(file).map_err(|e| {
+            AskError::new(
+                EXIT_USAGE,
+                format!("Cant read question file {}: {}", file, e),
+            )
+        })?
+    } else if !std::io::stdin().is_terminal() {
+        let mut question = String::new();
+        std::io::stdin()
+            .read_to_string(&mut question)
+            .map_err(|e| AskError::new(EXIT_USAGE, format!("Cant read stdin: {}", e)))?;
+        question
+    } else {
+        return Ok(None);
+    };
+    let question = question.trim();
+    if question.is_empty() {
+        return Err(AskError::new(
+            EXIT_USAGE,
+            "The question is empty".to_string(),
+        ));
+    }
+    Ok(Some(question.to_string()))
+}
+
+// In JSON mode stdout gets {"error": ..., "code": ...} so it is always valid JSON
+pub fn report_error(format: OutputFormat, error: &AskError) {
+    eprintln!("Error: {}", error.message);
+    if format == OutputFormat::Json {
+        println!(
+            "{}",
+            serde_json::json!({"error": error.message, "code": error.code})
+        );
+    }
+}
+
+pub struct AskAnswer {
+    pub answer: String,
+    pub files: Vec<(String, f32)>,
+    pub citations: Vec<Citation>,
+    pub retrieval_ms: u64,
+    pub llm_ms: u64,
+}
+
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    root: &'a str,
+    path: &'a str,
+    score: f32,
+}
+
+#[derive(Serialize)]
+struct JsonModel {
+    llm: String,
+    // None when --retrieval=lexical, no embeddings are computed then
+    embedding: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonTimings {
+    load: u64,
+    retrieval: u64,
+    llm: u64,
+    total: u64,
+}
+
+#[derive(Serialize)]
+struct JsonAnswer<'a> {
+    question: &'a str,
+    answer: &'a str,
+    files: Vec<JsonFile<'a>>,
+    citations: &'a [Citation],
+    model: JsonModel,
+    timings_ms: JsonTimings,
+}
+
+struct Turn {
+    question: String,
+    answer: String,
+}
+
+// One indexed folder of the session, e.g. the backend, a shared library and the
+// frontend repos are three roots. Each has its own index and index settings.
+struct AskRoot {
+    label: String,
+    path: String,
+    index: ProjectIndex,
+    // Lexical when this index has no embeddings
+    retrieval: Retrieval,
+}
+
+#[derive(Debug, PartialEq)]
+struct Scope {
+    label: String,
+    // Relative to the root
+    folder: Option<String>,
+}
+
+// "label=path" or just "path", labelled by its folder name. Labels are made unique.
+fn parse_roots(args: &[String]) -> Vec<(String, String)> {
+    let mut roots: Vec<(String, String)> = Vec::new();
+    for arg in args {
+        let (label, path) = match arg.split_once('=') {
+            Some((label, path)) if !label.is_empty() && !label.contains(['/', '\\']) => {
+                (label.to_string(), path.to_string())
+            }
+            _ => {
+                let canonical = std::fs::canonicalize(arg).unwrap_or_else(|_| arg.into());
+                let name = canonical
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| arg.clone());
+                (name, arg.clone())
+            }
+        };
+        let mut unique = label.clone();
+        let mut n = 2;
+        while roots.iter().any(|(l, _)| *l == unique) {
+            unique = format!("{}-{}", label, n);
+            n += 1;
+        }
+        roots.push((unique, path));
+    }
+    roots
+}
+
+// "backend" limits the search to that root, "backend/src/api" to a folder of it.
+// With a single root the label can be left out: "src/api".
+fn parse_scope(scope: &str, labels: &[&str]) -> Result<Scope, String> {
+    let scope = scope.trim().trim_end_matches('/');
+    let (first, rest) = match scope.split_once('/') {
+        Some((first, rest)) => (first, Some(rest.to_string())),
+        None => (scope, None),
+    };
+    if labels.contains(&first) {
+        return Ok(Scope {
+            label: first.to_string(),
+            folder: rest,
+        });
+    }
+    if labels.len() == 1 {
+        return Ok(Scope {
+            label: labels[0].to_string(),
+            folder: Some(scope.to_string()),
+        });
+    }
+    Err(format!(
+        "Unknown root in scope {}, the roots are: {}",
+        scope,
+        labels.join(", ")
+    ))
+}
+
+// Keeps the indexes loaded and the conversation so far, so follow-up questions
+// like "and where is that called?" are answered in context.
+pub struct AskSession<'a> {
+    llm: &'a LLMApi,
+    prompt: &'a Prompt,
+    roots: Vec<AskRoot>,
+    scope: Option<Scope>,
+    top_k: usize,
+    rerank: Option<Rerank>,
+    context_tokens: usize,
+    docs: bool,
+    rewrite: Option<Rewrite>,
+    history: Vec<Turn>,
+    last_files: Vec<(String, f32)>,
+}
+
+impl<'a> AskSession<'a> {
+    fn new(llm: &'a LLMApi, prompt: &'a Prompt, roots: Vec<AskRoot>, options: &AskOptions) -> Self {
+        AskSession {
+            llm,
+            prompt,
+            roots,
+            scope: None,
+            top_k: options.top_k,
+            rerank: options.rerank.clone(),
+            context_tokens: options.context_tokens,
+            docs: options.docs,
+            rewrite: options.rewrite,
+            history: vec![],
+            last_files: vec![],
+        }
+    }
+
+    fn answer(&mut self, question: &str, cache: &mut Cache) -> Result<AskAnswer, AskError> {
+        let retrieval_start = Instant::now();
+        // A follow-up alone is often too vague to find code, so search with the
+        // previous question as well
+        let query = match self.history.last() {
+            Some(turn) => format!("{}\r\n{}", turn.question, question),
+            None => question.to_string(),
+        };
+        let mut queries = vec![query.clone()];
+        if let Some(rewrite) = self.rewrite {
+            match query_rewrite::rewrite_query(&query, rewrite, self.llm, cache, self.prompt) {
+                Ok(rewritten) => {
+                    if *VERBOSE.lock().unwrap() {
+                        info!("Rewritten query:\r\n{}", rewritten);
+                    }
+                    queries.push(rewritten);
+                }
+                Err(e) => info!(
+                    "Warning: query rewrite failed, search with the question only: {}",
+                    e
+                ),
+            }
+        }
+        let candidates = match &self.rerank {
+            Some(rerank) => rerank.candidates.max(self.top_k),
+            None => self.top_k,
+        };
+        let uses_embeddings = self.roots.iter().any(|r| r.retrieval.uses_embeddings());
+        let mut rankings = Vec::new();
+        for text in &queries {
+            let target_emb = if uses_embeddings {
+                self.llm.emb(text, cache, text)
+            } else {
+                vec![]
+            };
+            if uses_embeddings && target_emb.is_empty() {
+                info!("Warning: no embedding for the question, use keyword search only");
+            }
+            self.check_dimensions(&target_emb)?;
+            let question_emb = if target_emb.is_empty() {
+                None
+            } else {
+                Some(target_emb.as_slice())
+            };
+            rankings.push(self.search(text, question_emb, candidates));
+        }
+        // Files found by both the question and its rewrite come first
+        let found = match rankings.len() {
+            1 => rankings.pop().unwrap(),
+            _ => reciprocal_rank_fusion(&rankings, candidates),
+        };
+        self.last_files = match &self.rerank {
+            Some(rerank) => rerank::rerank(
+                &query,
+                found,
+                self.top_k,
+                &rerank.reranker,
+                self.llm,
+                cache,
+                self.prompt,
+            ),
+            None => found,
+        };
+        let mut sources = Vec::new();
+        let mut files_content_vec = Vec::new();
+        for (k, _) in &self.last_files {
+            // A document chunk shows its lines with their numbers in the file
+            let Some((path, start, content)) = documents::read_source(k) else {
+                continue;
+            };
+            sources.push(SourceRange {
+                path: path.clone(),
+                start,
+                end: start + content.lines().count().max(1) - 1,
+            });
+            files_content_vec.push(format!(
+                "# {} \r\n{}",
+                path,
+                citations::number_lines(&content, start)
+            ));
+        }
+        if self.context_tokens > 0 {
+            let retrieved: Vec<String> = self.last_files.iter().map(|(f, _)| f.clone()).collect();
+            for (snippet, source) in self.related_context(&retrieved) {
+                files_content_vec.push(snippet);
+                sources.push(source);
+            }
+        }
+        let files_content = files_content_vec.join("\r\n");
+        let retrieval_ms = retrieval_start.elapsed().as_millis() as u64;
+
+        let prompt_template = format!(
+            "{}\r\n{}{}\r\n{}\r\n{}",
+            files_content,
+            history_prompt(&self.history, HISTORY_TOKEN_BUDGET),
+            citations::CITATION_PROMPT,
+            ASK_ANSWER_PROMPT,
+            question
+        );
+        if *VERBOSE.lock().unwrap() {
+            info!("Request: {}", prompt_template);
+        }
+        let llm_start = Instant::now();
+        let answer = self
+            .llm
+            .try_request(&prompt_template, &Vec::new(), cache, self.prompt)
+            .map_err(|e| AskError::new(EXIT_LLM, e))?;
+        let llm_ms = llm_start.elapsed().as_millis() as u64;
+        self.history.push(Turn {
+            question: question.to_string(),
+            answer: answer.clone(),
+        });
+        Ok(AskAnswer {
+            citations: citations::check_citations(&answer, &sources),
+            answer,
+            files: self.last_files.clone(),
+            retrieval_ms,
+            llm_ms,
+        })
+    }
+
+    fn check_dimensions(&self, emb: &[f32]) -> Result<(), AskError> {
+        for root in &self.roots {
+            if root.retrieval.uses_embeddings()
+                && !emb.is_empty()
+                && emb.len() != root.index.dimensions()
+            {
+                return Err(AskError::new(
+                    EXIT_INDEX,
+                    format!(
+                        "The question embedding has {} dimensions, the index of {} has {}",
+                        emb.len(),
+                        root.path,
+                        root.index.dimensions()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn search(&self, query: &str, question_emb: Option<&[f32]>, k: usize) -> Vec<(String, f32)> {
+        let mut rankings = Vec::new();
+        for root in &self.roots {
+            let folder = match &self.scope {
+                Some(scope) if scope.label != root.label => continue,
+                Some(scope) => scope.folder.as_ref(),
+                None => None,
+            };
+            let folder = folder.map(|f| Path::new(&root.path).join(f));
+            let keep = |id: &str| {
+                let file = documents::id_file(id);
+                (self.docs || !documents::is_document(file))
+                    && folder
+                        .as_ref()
+                        .is_none_or(|f| Path::new(file).starts_with(f))
+            };
+            rankings.push(
+                root.index
+                    .search(query, question_emb, root.retrieval, k, &keep),
+            );
+        }
+        match rankings.len() {
+            1 => rankings.pop().unwrap(),
+            // Scores of different indexes do not compare, ranks do
+            _ => reciprocal_rank_fusion(&rankings, k),
+        }
+    }
+
+    fn set_scope(&mut self, scope: &str) -> Result<(), String> {
+        let labels: Vec<&str> = self.roots.iter().map(|r| r.label.as_str()).collect();
+        self.scope = Some(parse_scope(scope, &labels)?);
+        Ok(())
+    }
+
+    fn root_of(&self, id: &str) -> Option<&AskRoot> {
+        let file = documents::id_file(id);
+        self.roots.iter().find(|r| r.index.hash(file).is_some())
+    }
+
+    // "[backend] ../backend/src/main.rs", no label when there is one root
+    fn display_path(&self, file: &str) -> String {
+        match self.root_of(file) {
+            Some(root) if self.roots.len() > 1 => format!("[{}] {}", root.label, file),
+            _ => file.to_string(),
+        }
+    }
+
+    // Definitions used by the retrieved files and code using theirs, from the best
+    // hit down, as long as they fit in the context budget
+    fn related_context(&self, retrieved: &[String]) -> Vec<(String, SourceRange)> {
+        let mut used = 0;
+        let mut context: Vec<(String, SourceRange)> = Vec::new();
+        let mut contents: HashMap<String, Vec<String>> = HashMap::new();
+        for file in retrieved {
+            let Some(root) = self.root_of(file) else {
+                continue;
+            };
+            for related in root.index.related(file, retrieved) {
+                let lines = contents.entry(related.file.clone()).or_insert_with(|| {
+                    std::fs::read_to_string(&related.file)
+                        .unwrap_or_default()
+                        .lines()
+                        .map(|l| l.to_string())
+                        .collect()
+                });
+                let start = related.start as usize;
+                let end = (related.end as usize).min(lines.len());
+                if start > end
+                    || context
+                        .iter()
+                        .any(|(_, s)| s.path == related.file && s.start == start && s.end == end)
+                {
+                    continue;
+                }
+                let label = match related.relation {
+                    Relation::Uses => "definition of",
+                    Relation::UsedBy => "uses",
+                };
+                let snippet = format!(
+                    "# {}:{}-{} ({} {})\r\n{}",
+                    related.file,
+                    start,
+                    end,
+                    label,
+                    related.name,
+                    citations::number_lines(&lines[start - 1..end].join("\n"), start)
+                );
+                let tokens = estimate_tokens(&snippet);
+                // A smaller definition further down may still fit
+                if used + tokens > self.context_tokens {
+                    continue;
+                }
+                used += tokens;
+                if *VERBOSE.lock().unwrap() {
+                    info!(
+                        "Add {}:{}-{} ({} {})",
+                        related.file, start, end, label, related.name
+                    );
+                }
+                context.push((
+                    snippet,
+                    SourceRange {
+                        path: related.file,
+                        start,
+                        end,
+                    },
+                ));
+            }
+        }
+        context
+    }
+}
+
+// Most recent turns first until the budget is spent, then printed oldest first
+fn history_prompt(history: &[Turn], budget: usize) -> String {
+    let mut selected = Vec::new();
+    let mut used = 0;
+    for turn in history.iter().rev() {
+        let text = format!("Question: {}\r\nAnswer: {}\r\n", turn.question, turn.answer);
+        let tokens = estimate_tokens(&text);
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        selected.push(text);
+    }
+    if selected.is_empty() {
+        return String::new();
+    }
+    selected.reverse();
+    format!("Previous conversation:\r\n{}\r\n", selected.join(""))
+}
+
+#[derive(Debug, PartialEq)]
+enum ReplCommand {
+    Files,
+    Reset,
+    TopK(usize),
+    // None clears the scope
+    Scope(Option<String>),
+    Help,
+    Exit,
+    Unknown(String),
+}
+
+fn parse_command(input: &str) -> Option<ReplCommand> {
+    let input = input.trim();
+    if !input.starts_with('/') {
+        return None;
+    }
+    let mut parts = input.split_whitespace();
+    let command = match parts.next().unwrap_or("") {
+        "/files" => ReplCommand::Files,
+        "/reset" => ReplCommand::Reset,
+        "/help" => ReplCommand::Help,
+        "/exit" | "/quit" => ReplCommand::Exit,
+        "/scope" => ReplCommand::Scope(parts.next().map(|s| s.to_string())),
+        "/k" => match parts.next().and_then(|k| k.parse().ok()) {
+            Some(k) if k > 0 => ReplCommand::TopK(k),
+            _ => ReplCommand::Unknown(input.to_string()),
+        },
+        _ => ReplCommand::Unknown(input.to_string()),
+    };
+    Some(command)
+}
+
+fn print_help() {
+    println!("Commands:");
+    println!("  /files   show the files used for the last answer");
+    println!("  /reset   forget the conversation");
+    println!("  /k N     use the N closest files for the next answers");
+    println!("  /scope X only search the root or folder X, /scope alone searches everything");
+    println!("  /exit    quit");
+}
+
+// "ask" does not build the index, it loads it and checks that it can be searched
+// with the current embedding model
+pub fn load_index(
+    path: &str,
+    llm: &LLMApi,
+    retrieval: Retrieval,
+) -> Result<(ProjectIndex, Retrieval), AskError> {
+    let index = ProjectIndex::load(path);
+    if index.is_empty() {
+        return Err(AskError::new(
+            EXIT_INDEX,
+            format!(
+                "No index for {}, build it with: rustsn index {}",
+                path, path
+            ),
+        ));
+    }
+    if !retrieval.uses_embeddings() {
+        return Ok((index, retrieval));
+    }
+    if index.embedding_model().is_empty() {
+        if retrieval == Retrieval::Semantic {
+            return Err(AskError::new(
+                EXIT_INDEX,
+                format!(
+                    "The index of {} has no embeddings, build it without --no-embeddings or use --retrieval lexical",
+                    path
+                ),
+            ));
+        }
+        info!("Warning: the index has no embeddings, use keyword search only");
+        return Ok((index, Retrieval::Lexical));
+    }
+    let (_, emb_model) = llm.model_names();
+    if index.embedding_model() != emb_model {
+        return Err(AskError::new(
+            EXIT_INDEX,
+            format!(
+                "The index of {} was built with the embedding model {}, not {}. Build it again with: rustsn index {}",
+                path,
+                index.embedding_model(),
+                emb_model,
+                path
+            ),
+        ));
+    }
+    Ok((index, retrieval))
+}
+
+pub fn handle_ask_command(
+    paths: &[String],
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+    options: AskOptions,
+    question: Option<String>,
+) -> Result<(), AskError> {
+    let start = Instant::now();
+    let mut roots = Vec::new();
+    for (label, path) in parse_roots(paths) {
+        let (index, retrieval) = load_index(&path, llm, options.retrieval)?;
+        roots.push(AskRoot {
+            label,
+            path,
+            index,
+            retrieval,
+        });
+    }
+    let load_ms = start.elapsed().as_millis() as u64;
+    let uses_embeddings = roots.iter().any(|r| r.retrieval.uses_embeddings());
+    let mut session = AskSession::new(llm, prompt, roots, &options);
+    if let Some(scope) = &options.scope {
+        session
+            .set_scope(scope)
+            .map_err(|e| AskError::new(EXIT_USAGE, e))?;
+    }
+
+    if let Some(question) = question {
+        let answer = session.answer(&question, cache)?;
+        match options.format {
+            OutputFormat::Json => {
+                let (llm_model, emb_model) = llm.model_names();
+                let output = JsonAnswer {
+                    question: &question,
+                    answer: &answer.answer,
+                    files: answer
+                        .files
+                        .iter()
+                        .map(|(path, score)| JsonFile {
+                            root: session.root_of(path).map_or("", |r| r.label.as_str()),
+                            path,
+                            score: *score,
+                        })
+                        .collect(),
+                    citations: &answer.citations,
+                    model: JsonModel {
+                        llm: llm_model,
+                        embedding: uses_embeddings.then_some(emb_model),
+                    },
+                    timings_ms: JsonTimings {
+                        load: load_ms,
+                        retrieval: answer.retrieval_ms,
+                        llm: answer.llm_ms,
+                        total: start.elapsed().as_millis() as u64,
+                    },
+                };
+                println!("{}", serde_json::to_string_pretty(&output).unwrap());
+            }
+            OutputFormat::Text => {
+                println!("{}", answer.answer.trim());
+                println!();
+                citations::print_citations(&answer.citations);
+            }
+        }
+        return Ok(());
+    }
+
+    println!("Enter the question about your project sources (/help for commands):");
+    while let Some(input) = read_question() {
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if let Some(command) = parse_command(input) {
+            match command {
+                ReplCommand::Files => {
+                    for (file, score) in &session.last_files {
+                        println!("{} ({:.4})", session.display_path(file), score);
+                    }
+                }
+                ReplCommand::Reset => {
+                    session.history.clear();
+                    session.last_files.clear();
+                    println!("Conversation is reset");
+                }
+                ReplCommand::TopK(k) => {
+                    session.top_k = k;
+                    println!("Use {} closest files", k);
+                }
+                ReplCommand::Scope(None) => {
+                    session.scope = None;
+                    println!("Search all roots");
+                }
+                ReplCommand::Scope(Some(scope)) => match session.set_scope(&scope) {
+                    Ok(()) => println!("Only search {}", scope),
+                    Err(e) => println!("{}", e),
+                },
+                ReplCommand::Help => print_help(),
+                ReplCommand::Exit => break,
+                ReplCommand::Unknown(command) => {
+                    println!("Unknown command: {}", command);
+                    print_help();
+                }
+            }
+            continue;
+        }
+
+        let answer = match session.answer(input, cache) {
+            Ok(answer) => answer,
+            Err(e) => {
+                println!("Error: {}", e.message);
+                println!("Enter the question again (/help for commands):");
+                continue;
+            }
+        };
+        println!("Find closest files:");
+        for (k, _v) in &answer.files {
+            println!("File: {}", session.display_path(k));
+        }
+        println!("++++++++ Answer ++++++++++++");
+        println!("Answer: {}", answer.answer);
+        println!();
+        citations::print_citations(&answer.citations);
+        println!();
+        println!("Enter a follow-up question (/help for commands):");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("how does it work?"), None);
+        assert_eq!(parse_command("/files"), Some(ReplCommand::Files));
+        assert_eq!(parse_command(" /reset "), Some(ReplCommand::Reset));
+        assert_eq!(parse_command("/k 5"), Some(ReplCommand::TopK(5)));
+        assert_eq!(
+            parse_command("/k zero"),
+            Some(ReplCommand::Unknown("/k zero".to_string()))
+        );
+        assert_eq!(parse_command("/quit"), Some(ReplCommand::Exit));
+        assert_eq!(
+            parse_command("/scope web/src"),
+            Some(ReplCommand::Scope(Some("web/src".to_string())))
+        );
+        assert_eq!(parse_command("/scope"), Some(ReplCommand::Scope(None)));
+    }
+
+    #[test]
+    fn test_parse_roots() {
+        let args: Vec<String> = ["api=../shop/api", "../shop/web", "../other/web"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let roots = parse_roots(&args);
+        let labels: Vec<&str> = roots.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(labels, vec!["api", "web", "web-2"]);
+        assert_eq!(roots[0].1, "../shop/api");
+        assert_eq!(roots[2].1, "../other/web");
+    }
+
+    #[test]
+    fn test_parse_scope() {
+        let labels = ["api", "web"];
+        assert_eq!(
+            parse_scope("web/src/pages/", &labels).unwrap(),
+            Scope {
+                label: "web".to_string(),
+                folder: Some("src/pages".to_string()),
+            }
+        );
+        assert_eq!(parse_scope("api", &labels).unwrap().folder, None);
+        assert!(parse_scope("src/pages", &labels).is_err());
+        // The label can be left out with a single root
+        assert_eq!(
+            parse_scope("src/pages", &["web"]).unwrap(),
+            Scope {
+                label: "web".to_string(),
+                folder: Some("src/pages".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_question_arg() {
+        let question = " where is the cache saved? \n".to_string();
+        assert_eq!(
+            read_question_arg(Some(&question), None).unwrap(),
+            Some("where is the cache saved?".to_string())
+        );
+        let empty = "  ".to_string();
+        assert_eq!(
+            read_question_arg(Some(&empty), None).unwrap_err().code,
+            EXIT_USAGE
+        );
+        let missing = "no/such/question.txt".to_string();
+        assert_eq!(
+            read_question_arg(None, Some(&missing)).unwrap_err().code,
+            EXIT_USAGE
+        );
+    }
+
+    #[test]
+    fn test_history_prompt_keeps_latest_turns_within_budget() {
+        let history: Vec<Turn> = (0..5)
+            .map(|i| Turn {
+                question: format!("question {}", i),
+                answer: "a".repeat(400),
+            })
+            .collect();
+        let prompt = history_prompt(&history, 250);
+        assert!(!prompt.contains("question 2"));
+        assert!(prompt.contains("question 3"));
+        assert!(prompt.contains("question 4"));
+        assert!(prompt.find("question 3") < prompt.find("question 4"));
+        assert_eq!(history_prompt(&history, 10), "");
+    }
+}
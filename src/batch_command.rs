@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use crate::ask_command::{AskError, EXIT_USAGE};
+use crate::cache::Cache;
+use crate::llm_api::LLMApi;
+use crate::llm_prompt::Prompt;
+use crate::llm_response::ProjectFile;
+use crate::session::Session;
+use crate::state_machine::{self, RunOptions, Target, EXIT_ATTEMPTS, EXIT_INFRA};
+use crate::toolchain::{self, SANDBOX_DIR};
+use crate::Lang;
+
+// One line of the task file
+#[derive(Debug, Deserialize)]
+struct TaskLine {
+    // Written back with the result, the line number when missing
+    id: Option<String>,
+    description: String,
+    // --lang when missing
+    lang: Option<String>,
+    // Tests the generated code has to pass, given to the LLM with the description
+    tests: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Task {
+    pub id: String,
+    // Line of the task file, also names the sandbox of the task
+    pub line: usize,
+    pub lang: Lang,
+    pub question: String,
+}
+
+pub struct BatchOptions {
+    // Tasks run at the same time
+    pub jobs: usize,
+    // JSON lines file receiving one result per task, in the order they finish
+    pub output: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskResult {
+    pub id: String,
+    pub line: usize,
+    pub lang: String,
+    // "succeeded", "failed" when the attempts ran out, "error" when the LLM, docker
+    // or the file system failed
+    pub status: &'static str,
+    pub reason: Option<String>,
+    pub attempts: u32,
+    pub tokens: usize,
+    pub duration_ms: u128,
+    // The last version of the code, also when it did not pass
+    pub files: Vec<ProjectFile>,
+    // Id of the saved run, see "session show"
+    pub session: Option<String>,
+}
+
+pub fn read_tasks(path: &str, default_lang: &Lang) -> Result<Vec<Task>, AskError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| AskError::new(EXIT_USAGE, format!("Cant read {}: {}", path, e)))?;
+    let mut tasks = Vec::new();
+    for (i, text) in content.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let line = i + 1;
+        let error =
+            |message: String| AskError::new(EXIT_USAGE, format!("{}:{}: {}", path, line, message));
+        let task: TaskLine = serde_json::from_str(text).map_err(|e| error(e.to_string()))?;
+        let lang = match &task.lang {
+            Some(lang) => lang.parse().map_err(error)?,
+            None => default_lang.clone(),
+        };
+        if toolchain::toolchain(&lang).is_none() {
+            return Err(error(toolchain::unsupported(&lang)));
+        }
+        let question = match &task.tests {
+            Some(tests) => format!(
+                "{}\n\nThe code must pass these tests:\n{}",
+                task.description.trim(),
+                tests.trim()
+            ),
+            None => task.description.trim().to_string(),
+        };
+        tasks.push(Task {
+            id: task.id.unwrap_or_else(|| line.to_string()),
+            line,
+            lang,
+            question,
+        });
+    }
+    Ok(tasks)
+}
+
+// Runs the generate loop of every task, `jobs` at a time, each task in its own sandbox
+// below SANDBOX_DIR. `prompts` holds the prompt of every language of the tasks.
+pub fn run_batch(
+    tasks: &[Task],
+    prompts: &HashMap<String, Prompt>,
+    options: &BatchOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+) -> Result<Vec<TaskResult>, AskError> {
+    let mut output = File::create(&options.output)
+        .map_err(|e| AskError::new(EXIT_USAGE, format!("Cant create {}: {}", options.output, e)))?;
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut results = Vec::new();
+    let workers = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..options.jobs.min(tasks.len()))
+            .map(|_| {
+                let sender = sender.clone();
+                let next = &next;
+                // Workers write to a copy of the cache, merged once they are done
+                let mut cache = cache.snapshot();
+                scope.spawn(move || {
+                    while let Some(task) = tasks.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        let prompt = &prompts[&task.lang.to_string()];
+                        let result = run_task(task, prompt, llm, &mut cache);
+                        if sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                    cache
+                })
+            })
+            .collect();
+        drop(sender);
+        for result in receiver {
+            info!(
+                "[{}/{}] {}: {} after {} rewrites in {} ms",
+                results.len() + 1,
+                tasks.len(),
+                result.id,
+                result.status,
+                result.attempts,
+                result.duration_ms
+            );
+            let line = serde_json::to_string(&result).unwrap();
+            // Flushed line by line so the results can be followed during the batch
+            if let Err(e) = writeln!(output, "{}", line).and_then(|_| output.flush()) {
+                eprintln!("Cant write the result: {}", e);
+            }
+            results.push(result);
+        }
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<Cache>>()
+    });
+    for worker in workers {
+        cache.merge(worker);
+    }
+    Ok(results)
+}
+
+fn run_task(task: &Task, prompt: &Prompt, llm: &LLMApi, cache: &mut Cache) -> TaskResult {
+    let mut options = RunOptions::new(Target::Function);
+    options.sandbox = sandbox(task);
+    options.console = false;
+    let mut result = TaskResult {
+        id: task.id.clone(),
+        line: task.line,
+        lang: task.lang.to_string(),
+        status: "error",
+        reason: None,
+        attempts: 0,
+        tokens: 0,
+        duration_ms: 0,
+        files: Vec::new(),
+        session: None,
+    };
+    match Session::create(&task.lang, "function", None, &task.question) {
+        Ok(session) => {
+            result.session = Some(session.manifest.id.clone());
+            options.session = Some(session);
+        }
+        Err(e) => {
+            result.reason = Some(e);
+            return result;
+        }
+    }
+    match state_machine::run_state_machine(&task.lang, &task.question, prompt, cache, llm, &options)
+    {
+        Ok((report, project)) => {
+            result.status = status(state_machine::exit_code(&report));
+            result.reason = report.reason;
+            result.attempts = report.attempts;
+            result.tokens = report.tokens;
+            result.duration_ms = report.duration_ms;
+            result.files = project.files;
+        }
+        Err(e) => result.reason = Some(e.message),
+    }
+    result
+}
+
+fn sandbox(task: &Task) -> PathBuf {
+    Path::new(SANDBOX_DIR)
+        .join("batch")
+        .join(task.line.to_string())
+}
+
+fn status(exit_code: i32) -> &'static str {
+    match exit_code {
+        0 => "succeeded",
+        EXIT_ATTEMPTS => "failed",
+        _ => "error",
+    }
+}
+
+// 0 when every task passed, like "generate" otherwise: EXIT_INFRA wins over EXIT_ATTEMPTS
+pub fn exit_code(results: &[TaskResult]) -> i32 {
+    if results.iter().any(|r| r.status == "error") {
+        EXIT_INFRA
+    } else if results.iter().any(|r| r.status == "failed") {
+        EXIT_ATTEMPTS
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_tasks() {
+        let path = std::env::temp_dir().join("rustsn_test_read_tasks.jsonl");
+        let lines = [
+            r#"{"id": "add", "description": "add two numbers", "lang": "python"}"#,
+            "",
+            r#"{"description": "reverse a string", "tests": "assert reverse(\"ab\") == \"ba\""}"#,
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let tasks = read_tasks(path.to_str().unwrap(), &Lang::Rust).unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].id, "add");
+        assert_eq!(tasks[0].lang, Lang::Python);
+        assert_eq!(tasks[1].id, "3");
+        assert_eq!(tasks[1].lang, Lang::Rust);
+        assert!(tasks[1]
+            .question
+            .ends_with("assert reverse(\"ab\") == \"ba\""));
+        assert_eq!(sandbox(&tasks[1]), Path::new("sandbox/batch/3"));
+
+        std::fs::write(&path, r#"{"description": "x", "lang": "cobol"}"#).unwrap();
+        let error = read_tasks(path.to_str().unwrap(), &Lang::Rust).unwrap_err();
+        assert_eq!(error.code, EXIT_USAGE);
+        assert!(
+            error.message.ends_with(":1: Unsupported language: cobol"),
+            "{}",
+            error.message
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,16 +1,19 @@
-use bincode::{config, Decode, Encode};
+use bincode::config;
 use std::collections::HashMap;
 use std::fs;
 
-#[derive(Encode, Decode, PartialEq, Debug)]
+#[derive(PartialEq, Debug, Default)]
 pub struct Cache {
     cache: HashMap<String, String>,
+    // Saved to cache.bin on every change; false for the copies of "batch" workers
+    persist: bool,
 }
 
 impl Cache {
     pub fn new() -> Cache {
         let mut cache = Cache {
             cache: HashMap::new(),
+            persist: true,
         };
         cache.restore();
         cache
@@ -22,12 +25,30 @@ impl Cache {
 
     pub fn set(&mut self, key: String, value: String) {
         self.cache.insert(key, value);
-        self.save();
+        if self.persist {
+            self.save();
+        }
+    }
+
+    // In-memory copy for a worker thread, its new entries are added back with merge()
+    pub fn snapshot(&self) -> Cache {
+        Cache {
+            cache: self.cache.clone(),
+            persist: false,
+        }
+    }
+
+    pub fn merge(&mut self, other: Cache) {
+        self.cache.extend(other.cache);
+        if self.persist {
+            self.save();
+        }
     }
 
+    // Only the map is written, the same bytes as the former derived encoding of Cache
     fn save(&mut self) {
         let config = config::standard();
-        let encoded: Vec<u8> = bincode::encode_to_vec(&*self, config).unwrap();
+        let encoded: Vec<u8> = bincode::encode_to_vec(&self.cache, config).unwrap();
         fs::write("cache.bin", encoded).unwrap();
     }
 
@@ -37,8 +58,9 @@ impl Cache {
         }
         let config = config::standard();
         let encoded = fs::read("cache.bin").unwrap();
-        let (decoded, _): (Cache, usize) = bincode::decode_from_slice(&encoded, config).unwrap();
-        *self = decoded;
+        let (decoded, _): (HashMap<String, String>, usize) =
+            bincode::decode_from_slice(&encoded, config).unwrap();
+        self.cache = decoded;
     }
 }
 
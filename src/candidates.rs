@@ -0,0 +1,170 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::path::Path;
+
+use crate::cache::Cache;
+use crate::llm_response::Project;
+use crate::state_machine::NO_FILES;
+use crate::toolchain::{self, Toolchain};
+
+// "3 passed" in the summaries of cargo test, pytest and jest
+static RE_PASSED: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+) passed").unwrap());
+
+// One of the first generations of "generate --candidates", built and tested in its own sandbox
+pub struct Candidate {
+    pub index: usize,
+    pub project: Project,
+    // Estimated tokens of the prompt and the answer
+    pub tokens: usize,
+    // The raw answer of the LLM
+    pub response: String,
+    // Errors of the build, None when it passed
+    pub build_output: Option<String>,
+    pub test_passed: bool,
+    pub test_output: String,
+}
+
+impl Candidate {
+    pub fn passed(&self) -> bool {
+        self.build_output.is_none() && self.test_passed
+    }
+
+    // As far as the test output tells, 0 when it has no summary
+    pub fn passed_tests(&self) -> usize {
+        RE_PASSED
+            .captures_iter(&self.test_output)
+            .filter_map(|c| c[1].parse().ok())
+            .max()
+            .unwrap_or(0)
+    }
+
+    // For the candidates that did not pass: the ones that build first, then the most
+    // tests passed
+    fn score(&self) -> (bool, usize) {
+        (self.build_output.is_none(), self.passed_tests())
+    }
+}
+
+// Samples `count` answers with sample(index, cache), which returns the project, its
+// tokens and the raw answer, then builds and tests every answer in <sandbox>/candidates/<index>, all at the
+// same time. The results are in index order.
+pub fn run<F>(
+    count: usize,
+    sandbox: &Path,
+    toolchain: &dyn Toolchain,
+    cache: &mut Cache,
+    sample: F,
+) -> Vec<Result<Candidate, String>>
+where
+    F: Fn(usize, &mut Cache) -> Result<(Project, usize, String), String> + Sync,
+{
+    let sample = &sample;
+    let (results, caches): (Vec<_>, Vec<_>) = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..count)
+            .map(|index| {
+                // Every candidate writes to a copy of the cache, merged once they are done
+                let mut cache = cache.snapshot();
+                scope.spawn(move || {
+                    let result = check(index, sandbox, toolchain, &mut cache, sample);
+                    (result, cache)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .unzip()
+    });
+    for worker in caches {
+        cache.merge(worker);
+    }
+    results
+}
+
+fn check<F>(
+    index: usize,
+    sandbox: &Path,
+    toolchain: &dyn Toolchain,
+    cache: &mut Cache,
+    sample: &F,
+) -> Result<Candidate, String>
+where
+    F: Fn(usize, &mut Cache) -> Result<(Project, usize, String), String>,
+{
+    let (project, tokens, response) = sample(index, cache)?;
+    if project.files.is_empty() {
+        return Err(NO_FILES.to_string());
+    }
+    let sandbox = sandbox.join("candidates").join(index.to_string());
+    toolchain::create_project(&project, &sandbox)?;
+    let (built, build_output) = toolchain.build(&project, &sandbox, cache)?;
+    // Tested even when the build fails, like in the state machine
+    let (test_passed, test_output) = toolchain.test(&project, &sandbox, cache)?;
+    Ok(Candidate {
+        index,
+        project,
+        tokens,
+        response,
+        build_output: (!built).then_some(build_output),
+        test_passed,
+        test_output,
+    })
+}
+
+// The first candidate that passed, otherwise the best scoring one; None when none of
+// them could be built and tested
+pub fn pick(candidates: &[Result<Candidate, String>]) -> Option<&Candidate> {
+    let built = candidates.iter().filter_map(|c| c.as_ref().ok());
+    if let Some(passed) = built.clone().find(|c| c.passed()) {
+        return Some(passed);
+    }
+    built.max_by_key(|c| (c.score(), Reverse(c.index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(index: usize, build: bool, test: bool, test_output: &str) -> Candidate {
+        Candidate {
+            index,
+            project: Project::default(),
+            tokens: 0,
+            response: String::new(),
+            build_output: (!build).then(|| "error".to_string()),
+            test_passed: test,
+            test_output: test_output.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_pick() {
+        let candidates = vec![
+            Err("LLM request failed: timeout".to_string()),
+            Ok(candidate(1, false, false, "")),
+            Ok(candidate(
+                2,
+                true,
+                false,
+                "test result: FAILED. 3 passed; 1 failed",
+            )),
+            Ok(candidate(
+                3,
+                true,
+                false,
+                "=== 1 failed, 4 passed in 0.02s ===",
+            )),
+        ];
+        assert_eq!(pick(&candidates).unwrap().index, 3);
+        assert_eq!(candidates[2].as_ref().unwrap().passed_tests(), 3);
+
+        // The first passing one, even with fewer tests than a later one
+        let mut candidates = candidates;
+        candidates.push(Ok(candidate(4, true, true, "2 passed")));
+        candidates.push(Ok(candidate(5, true, true, "5 passed")));
+        assert_eq!(pick(&candidates).unwrap().index, 4);
+
+        assert!(pick(&[Err(NO_FILES.to_string())]).is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+// path:12, path:12-30 or path:L12-L30
+static RE_CITATION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"([\w./\\-]+\.[A-Za-z0-9]+):L?(\d+)(?:\s*[-–]\s*L?(\d+))?").unwrap());
+
+pub const CITATION_PROMPT: &str = "Cite every part of the code your answer relies on as path:start-end using the file paths and line numbers shown above, for example src/main.rs:10-25.";
+
+// Lines of a file that were shown to the LLM
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceRange {
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Citation {
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+    // false when the range was not part of the retrieved code, i.e. made up
+    pub valid: bool,
+}
+
+// Prefixes every line with its number so the LLM can cite exact ranges
+pub fn number_lines(content: &str, first_line: usize) -> String {
+    let last_line = first_line + content.lines().count();
+    let width = last_line.to_string().len();
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| format!("{:>width$}| {}", first_line + i, line, width = width))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+pub fn check_citations(answer: &str, sources: &[SourceRange]) -> Vec<Citation> {
+    let mut citations: Vec<Citation> = Vec::new();
+    for cap in RE_CITATION.captures_iter(answer) {
+        let path = cap[1].trim_start_matches("./").to_string();
+        let start: usize = match cap[2].parse() {
+            Ok(start) => start,
+            Err(_) => continue,
+        };
+        let end = cap
+            .get(3)
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(start);
+        let valid = start >= 1
+            && start <= end
+            && sources.iter().any(|source| {
+                same_file(&source.path, &path) && source.start <= start && end <= source.end
+            });
+        let citation = Citation {
+            path,
+            start,
+            end,
+            valid,
+        };
+        if !citations.contains(&citation) {
+            citations.push(citation);
+        }
+    }
+    citations
+}
+
+// The LLM often shortens "../project/src/lib.rs" to "src/lib.rs"
+fn same_file(source: &str, cited: &str) -> bool {
+    let source = source.replace('\\', "/");
+    let cited = cited.replace('\\', "/");
+    source == cited || source.ends_with(&format!("/{}", cited))
+}
+
+pub fn print_citations(citations: &[Citation]) {
+    if citations.is_empty() {
+        println!("Warning: the answer does not cite any code");
+        return;
+    }
+    println!("Citations:");
+    for citation in citations {
+        println!(
+            "  {}:{}-{} {}",
+            citation.path,
+            citation.start,
+            citation.end,
+            if citation.valid {
+                "ok"
+            } else {
+                "NOT FOUND in the retrieved code"
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources() -> Vec<SourceRange> {
+        vec![
+            SourceRange {
+                path: "../shiva/lib/src/pdf.rs".to_string(),
+                start: 1,
+                end: 120,
+            },
+            SourceRange {
+                path: "src/main.rs".to_string(),
+                start: 1,
+                end: 40,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_number_lines() {
+        let numbered = number_lines("fn a() {\n}\n", 9);
+        assert_eq!(numbered, " 9| fn a() {\r\n10| }");
+    }
+
+    #[test]
+    fn test_check_citations() {
+        let answer = "The parser lives in `src/pdf.rs:10-25` and is called from src/main.rs:L12. \
+                      See also src/main.rs:30-90 and src/other.rs:1-2 and src/pdf.rs:10-25.";
+        let citations = check_citations(answer, &sources());
+        assert_eq!(citations.len(), 4);
+        assert_eq!(
+            citations[0],
+            Citation {
+                path: "src/pdf.rs".to_string(),
+                start: 10,
+                end: 25,
+                valid: true
+            }
+        );
+        assert!(citations[1].valid);
+        assert_eq!(citations[1].end, 12);
+        assert!(!citations[2].valid);
+        assert!(!citations[3].valid);
+    }
+}
@@ -122,57 +122,3 @@ pub fn create_container(lang: &Lang) -> Result<bool, String> {
         };
     })
 }
-
-use bollard::exec::{CreateExecOptions, StartExecResults};
-use bollard::container::LogOutput;
-use futures_util::StreamExt;
-
-pub async fn inspect_path () -> Result<(), String> {
-        // Подключаемся к Docker
-        let docker = Docker::connect_with_local_defaults()
-        .map_err(|e| format!("Failed to connect to Docker: {}", e))?;
-
-    // Получаем имя контейнера (предполагаем, что используем Rust контейнер)
-    let container_name = "rustsn_rust_container";
-
-    // Создаем конфигурацию для выполнения команды
-    let exec_config = CreateExecOptions {
-        cmd: Some(vec!["ls", "-la"]),
-        attach_stdout: Some(true),
-        attach_stderr: Some(true),
-        working_dir: Some("/app"),
-        user: Some("1000:1000"),
-        ..Default::default()
-    };
-
-    dbg!("FROM CREATE CONTAINER {:?}",&exec_config);
-
-    // Создаем exec процесс
-    let exec = docker.create_exec(container_name, exec_config)
-        .await
-        .map_err(|e| format!("Failed to create exec: {}", e))?;
-
-    // Запускаем exec процесс
-    match docker.start_exec(&exec.id, None).await {
-        Ok(StartExecResults::Attached { mut output, .. }) => {
-            while let Some(chunk) = output.next().await {
-                match chunk {
-                    Ok(LogOutput::StdOut { message }) => {
-                        println!("Current working directory: {}", String::from_utf8_lossy(&message));
-                    }
-                    Ok(LogOutput::StdErr { message }) => {
-                        eprintln!("Error: {}", String::from_utf8_lossy(&message));
-                    }
-                    _ => {}
-                }
-            }
-            Ok(())
-        }
-        Ok(StartExecResults::Detached) => {
-            Err("Unexpected detached mode".to_string())
-        }
-        Err(e) => {
-            Err(format!("Failed to start exec: {}", e))
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,284 @@
+// Documentation and config files indexed with "index --docs". A README or a
+// migration is long and covers many topics, so each one is split into chunks
+// that are embedded and retrieved on their own.
+pub const DOC_EXTENSIONS: &[&str] = &["md", "markdown", "yaml", "yml", "sql"];
+
+// Longer sections are cut, shorter YAML keys and SQL statements are merged up to it
+const MAX_CHUNK_LINES: usize = 60;
+
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    // 1 based, inclusive
+    pub start: usize,
+    pub end: usize,
+    // Where the chunk sits, e.g. "Setup > Docker" or "paths", indexed with its lines
+    pub context: String,
+    pub text: String,
+}
+
+pub fn is_document(file: &str) -> bool {
+    extension(file).is_some_and(|ext| DOC_EXTENSIONS.contains(&ext.as_str()))
+}
+
+// Id of a chunk in the index: "docs/adr/0003-cache.md#L12-40"
+pub fn chunk_id(file: &str, start: usize, end: usize) -> String {
+    format!("{}#L{}-{}", file, start, end)
+}
+
+// File and line range of a chunk id, None for the id of a whole file
+pub fn parse_chunk_id(id: &str) -> Option<(&str, usize, usize)> {
+    let (file, range) = id.rsplit_once("#L")?;
+    let (start, end) = range.split_once('-')?;
+    Some((file, start.parse().ok()?, end.parse().ok()?))
+}
+
+pub fn id_file(id: &str) -> &str {
+    parse_chunk_id(id).map_or(id, |(file, _, _)| file)
+}
+
+// The file, first line number and lines of an index id as shown to the LLM
+pub fn read_source(id: &str) -> Option<(String, usize, String)> {
+    match parse_chunk_id(id) {
+        Some((file, start, end)) => {
+            let content = std::fs::read_to_string(file).ok()?;
+            let lines: Vec<&str> = content
+                .lines()
+                .skip(start - 1)
+                .take(end + 1 - start)
+                .collect();
+            Some((file.to_string(), start, lines.join("\n")))
+        }
+        None => Some((id.to_string(), 1, std::fs::read_to_string(id).ok()?)),
+    }
+}
+
+pub fn chunk_document(file: &str, content: &str) -> Vec<Chunk> {
+    let lines: Vec<&str> = content.lines().collect();
+    let sections = match extension(file).as_deref() {
+        Some("md") | Some("markdown") => markdown_sections(&lines),
+        Some("yaml") | Some("yml") => merge_small(yaml_sections(&lines, 0, lines.len(), 0, "")),
+        Some("sql") => merge_small(sql_sections(&lines)),
+        _ => vec![(0, lines.len(), String::new())],
+    };
+    let mut chunks = Vec::new();
+    for (start, end, context) in sections {
+        let Some((start, end)) = trim_blank(&lines, start, end) else {
+            continue;
+        };
+        let mut window = start;
+        while window < end {
+            let window_end = (window + MAX_CHUNK_LINES).min(end);
+            let body = lines[window..window_end].join("\n");
+            chunks.push(Chunk {
+                start: window + 1,
+                end: window_end,
+                text: if context.is_empty() {
+                    body
+                } else {
+                    format!("{}\n{}", context, body)
+                },
+                context: context.clone(),
+            });
+            window = window_end;
+        }
+    }
+    chunks
+}
+
+fn extension(file: &str) -> Option<String> {
+    std::path::Path::new(file)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+// Sections are (first line, end line exclusive, context), 0 based
+type Section = (usize, usize, String);
+
+// One section per heading, with the titles of its parent headings as context.
+// Lines in fenced code blocks are not headings, e.g. "# comment" in a shell example.
+fn markdown_sections(lines: &[&str]) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut start = 0;
+    let mut context = String::new();
+    let mut in_code = false;
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            continue;
+        }
+        let level = line.chars().take_while(|c| *c == '#').count();
+        if !(1..=6).contains(&level) || !line[level..].starts_with(' ') {
+            continue;
+        }
+        sections.push((start, i, context));
+        while parents.last().is_some_and(|(l, _)| *l >= level) {
+            parents.pop();
+        }
+        context = parents
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .collect::<Vec<_>>()
+            .join(" > ");
+        parents.push((level, line[level..].trim().to_string()));
+        start = i;
+    }
+    sections.push((start, lines.len(), context));
+    sections
+}
+
+fn is_yaml_key(line: &str, indent: usize) -> bool {
+    let trimmed = line.trim_start();
+    line.len() - trimmed.len() == indent
+        && !trimmed.starts_with('#')
+        && !trimmed.starts_with('-')
+        && trimmed.contains(':')
+}
+
+// Split at the keys of one nesting level. A key too long for one chunk, like
+// "paths" of an OpenAPI spec, is split again at its own keys.
+fn yaml_sections(
+    lines: &[&str],
+    from: usize,
+    to: usize,
+    indent: usize,
+    context: &str,
+) -> Vec<Section> {
+    if from >= to {
+        return Vec::new();
+    }
+    let mut starts: Vec<usize> = (from..to)
+        .filter(|i| is_yaml_key(lines[*i], indent) || lines[*i].trim() == "---")
+        .collect();
+    if starts.first() != Some(&from) {
+        starts.insert(0, from);
+    }
+    let mut sections = Vec::new();
+    for (n, start) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).copied().unwrap_or(to);
+        let child_indent = lines[start + 1..end]
+            .iter()
+            .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .map(|l| l.len() - l.trim_start().len())
+            .filter(|child| *child > indent);
+        match child_indent {
+            Some(child) if end - start > MAX_CHUNK_LINES && is_yaml_key(lines[*start], indent) => {
+                let key = lines[*start].trim().trim_end_matches(':');
+                let child_context = if context.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{} > {}", context, key)
+                };
+                sections.push((*start, start + 1, context.to_string()));
+                sections.extend(yaml_sections(lines, start + 1, end, child, &child_context));
+            }
+            _ => sections.push((*start, end, context.to_string())),
+        }
+    }
+    sections
+}
+
+// One section per statement, ending at a line that ends with ";"
+fn sql_sections(lines: &[&str]) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim_end().ends_with(';') {
+            sections.push((start, i + 1, String::new()));
+            start = i + 1;
+        }
+    }
+    sections.push((start, lines.len(), String::new()));
+    sections
+}
+
+// Consecutive sections with the same context are merged while they fit in one chunk
+fn merge_small(sections: Vec<Section>) -> Vec<Section> {
+    let mut merged: Vec<Section> = Vec::new();
+    for (start, end, context) in sections {
+        match merged.last_mut() {
+            Some(last) if last.2 == context && end - last.0 <= MAX_CHUNK_LINES => last.1 = end,
+            _ => merged.push((start, end, context)),
+        }
+    }
+    merged
+}
+
+// The section without its leading and trailing blank lines, None when it is all blank
+fn trim_blank(lines: &[&str], start: usize, end: usize) -> Option<(usize, usize)> {
+    let first = (start..end).find(|i| !lines[*i].trim().is_empty())?;
+    let last = (start..end).rev().find(|i| !lines[*i].trim().is_empty())?;
+    Some((first, last + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(chunks: &[Chunk]) -> Vec<(usize, usize, &str)> {
+        chunks
+            .iter()
+            .map(|c| (c.start, c.end, c.context.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_markdown_chunks() {
+        let content = "# Guide\nIntro\n\n## Install\n```bash\n# not a heading\ncargo install rustsn\n```\n### Docker\nRun it\n## Usage\nAsk\n";
+        let chunks = chunk_document("README.md", content);
+        assert_eq!(
+            ranges(&chunks),
+            vec![
+                (1, 2, ""),
+                (4, 8, "Guide"),
+                (9, 10, "Guide > Install"),
+                (11, 12, "Guide"),
+            ]
+        );
+        assert_eq!(chunks[2].text, "Guide > Install\n### Docker\nRun it");
+    }
+
+    #[test]
+    fn test_yaml_chunks() {
+        let mut content = "openapi: 3.0.0\ninfo:\n  title: Shop\npaths:\n".to_string();
+        for n in 0..MAX_CHUNK_LINES {
+            content.push_str(&format!(
+                "  /items/{}:\n    get:\n      summary: Item {}\n",
+                n, n
+            ));
+        }
+        assert!(chunk_document("empty.yml", "").is_empty());
+        let chunks = chunk_document("api/openapi.yaml", &content);
+        assert_eq!((chunks[0].start, chunks[0].end), (1, 4));
+        assert!(chunks[1..].iter().all(|c| c.context == "paths"));
+        assert!(chunks
+            .iter()
+            .all(|c| c.end + 1 - c.start <= MAX_CHUNK_LINES));
+        // Every line is in exactly one chunk
+        let covered: usize = chunks.iter().map(|c| c.end + 1 - c.start).sum();
+        assert_eq!(covered, content.lines().count());
+        assert!(chunks[1].text.starts_with("paths\n  /items/0:"));
+    }
+
+    #[test]
+    fn test_sql_chunks_and_ids() {
+        let mut content = String::from(
+            "-- users\nCREATE TABLE users (\n  id INT\n);\nCREATE INDEX users_id ON users (id);\n",
+        );
+        content.push_str(&"INSERT INTO users VALUES (1);\n".repeat(MAX_CHUNK_LINES));
+        let chunks = chunk_document("migrations/001.sql", &content);
+        assert_eq!((chunks[0].start, chunks[0].end), (1, MAX_CHUNK_LINES));
+        assert_eq!(chunks[1].end, MAX_CHUNK_LINES + 5);
+
+        let id = chunk_id("docs/a#b.md", 12, 40);
+        assert_eq!(id, "docs/a#b.md#L12-40");
+        assert_eq!(parse_chunk_id(&id), Some(("docs/a#b.md", 12, 40)));
+        assert_eq!(id_file(&id), "docs/a#b.md");
+        assert_eq!(id_file("src/main.rs"), "src/main.rs");
+        assert!(is_document("docs/ADR.MD") && !is_document("src/main.rs"));
+    }
+}
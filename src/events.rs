@@ -0,0 +1,358 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::llm_response::ProjectFile;
+use crate::VERBOSE;
+
+// What happened during a "generate" run, emitted by the state machine driver
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    // The driver moved to `state`; attempt 0 is the first generation, then one per rewrite
+    Entered {
+        state: &'static str,
+        attempt: u32,
+    },
+    // The LLM answered the "generate" or "rewrite" prompt of `attempt`, `tokens` of
+    // prompt and answer; `request` is the prompt as sent, `candidate` the index of
+    // the answer with --candidates
+    Requested {
+        prompt: &'static str,
+        attempt: u32,
+        candidate: Option<usize>,
+        tokens: usize,
+        request: String,
+        response: String,
+    },
+    // One of the first generations of --candidates was built and tested; `error` when
+    // the LLM failed, its answer has no files or it could not be built or tested at all
+    Candidate {
+        index: usize,
+        build: bool,
+        test: bool,
+        passed_tests: usize,
+        error: Option<String>,
+    },
+    // The candidate the run goes on with
+    Picked {
+        index: usize,
+    },
+    // The sandbox project was written
+    Materialized {
+        files: Vec<ProjectFile>,
+        install_command: Option<String>,
+        build_command: String,
+        test_command: String,
+    },
+    // Build, test or lint finished; `output` holds the errors
+    Checked {
+        state: &'static str,
+        success: bool,
+        output: String,
+    },
+    // Lint has no command for this language
+    Skipped {
+        state: &'static str,
+    },
+    // The files were written to the project of "generate --into" or to --output-dir
+    Applied {
+        files: Vec<String>,
+    },
+    Finished {
+        success: bool,
+        reason: Option<String>,
+        attempts: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    // Milliseconds since the Unix epoch
+    pub at_ms: u128,
+    // Milliseconds since the start of the run
+    pub elapsed_ms: u128,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+pub trait EventSink {
+    fn on_event(&mut self, event: &Event);
+}
+
+// Stamps the events, passes them to the sinks and keeps them for the report
+pub struct EventLog {
+    started: Instant,
+    events: Vec<Event>,
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventLog {
+    pub fn new(sinks: Vec<Box<dyn EventSink>>) -> EventLog {
+        EventLog {
+            started: Instant::now(),
+            events: Vec::new(),
+            sinks,
+        }
+    }
+
+    pub fn emit(&mut self, kind: EventKind) {
+        let event = Event {
+            at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            elapsed_ms: self.started.elapsed().as_millis(),
+            kind,
+        };
+        for sink in self.sinks.iter_mut() {
+            sink.on_event(&event);
+        }
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+}
+
+// Progress of the run for the person at the terminal
+pub struct ConsoleSink;
+
+impl EventSink for ConsoleSink {
+    fn on_event(&mut self, event: &Event) {
+        match &event.kind {
+            EventKind::Entered { state, attempt } => {
+                info!(
+                    "[{}s] {} (attempt {})",
+                    event.elapsed_ms / 1000,
+                    state,
+                    attempt
+                )
+            }
+            EventKind::Requested { tokens, .. } => info!("LLM answered, ~{} tokens", tokens),
+            EventKind::Candidate {
+                index,
+                error: Some(error),
+                ..
+            } => info!("candidate {} failed: {}", index, error),
+            EventKind::Candidate {
+                index,
+                build,
+                test,
+                passed_tests,
+                ..
+            } => info!(
+                "candidate {}: build {}, test {}, {} tests passed",
+                index,
+                if *build { "passed" } else { "failed" },
+                if *test { "passed" } else { "failed" },
+                passed_tests
+            ),
+            EventKind::Picked { index } => info!("Go on with candidate {}", index),
+            // The contents only with --verbose, the events file has them anyway
+            EventKind::Materialized { files, .. } => {
+                info!("Create sandbox project with");
+                let verbose = *VERBOSE.lock().unwrap();
+                for file in files {
+                    if verbose {
+                        info!("# {}\n{}", file.path, file.content);
+                    } else {
+                        info!("# {}", file.path);
+                    }
+                }
+            }
+            EventKind::Checked {
+                state,
+                success: true,
+                ..
+            } => info!("{} passed", state),
+            EventKind::Checked { state, output, .. } => info!("{} failed:\n{}", state, output),
+            EventKind::Skipped { state } => info!("{} skipped", state),
+            EventKind::Applied { files } => {
+                for file in files {
+                    info!("Updated {}", file);
+                }
+            }
+            EventKind::Finished {
+                success: true,
+                attempts,
+                ..
+            } => info!("Succeeded after {} rewrites", attempts),
+            EventKind::Finished { reason, .. } => {
+                info!("Failed: {}", reason.as_deref().unwrap_or("unknown reason"))
+            }
+        }
+    }
+}
+
+// One JSON object per line, for tools following the run
+pub struct JsonLinesSink {
+    file: File,
+}
+
+impl JsonLinesSink {
+    pub fn create(path: &str) -> Result<JsonLinesSink, String> {
+        let file = File::create(path).map_err(|e| format!("Cant create {}: {}", path, e))?;
+        Ok(JsonLinesSink { file })
+    }
+}
+
+impl EventSink for JsonLinesSink {
+    fn on_event(&mut self, event: &Event) {
+        let line = serde_json::to_string(event).unwrap();
+        // Flushed line by line so the stream can be tailed during the run
+        if let Err(e) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            eprintln!("Cant write the event: {}", e);
+        }
+    }
+}
+
+// Summary of a run, built from its events
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub success: bool,
+    pub reason: Option<String>,
+    pub attempts: u32,
+    // Estimated tokens of the LLM prompts and answers
+    pub tokens: usize,
+    pub duration_ms: u128,
+    // Time spent in each state, in the order the states were first entered
+    pub states: Vec<StateTime>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct StateTime {
+    pub state: &'static str,
+    pub entered: u32,
+    pub ms: u128,
+}
+
+impl Report {
+    pub fn from_events(events: &[Event]) -> Report {
+        let mut states: Vec<StateTime> = Vec::new();
+        let mut positions: HashMap<&'static str, usize> = HashMap::new();
+        let mut current: Option<(&'static str, u128)> = None;
+        let mut report = Report {
+            success: false,
+            reason: None,
+            attempts: 0,
+            tokens: 0,
+            duration_ms: events.last().map(|e| e.elapsed_ms).unwrap_or(0),
+            states: Vec::new(),
+        };
+        for event in events {
+            match &event.kind {
+                EventKind::Entered { state, .. } => {
+                    if let Some((previous, since)) = current.take() {
+                        states[positions[previous]].ms += event.elapsed_ms - since;
+                    }
+                    let position = *positions.entry(state).or_insert_with(|| {
+                        states.push(StateTime {
+                            state,
+                            entered: 0,
+                            ms: 0,
+                        });
+                        states.len() - 1
+                    });
+                    states[position].entered += 1;
+                    current = Some((state, event.elapsed_ms));
+                }
+                EventKind::Requested { tokens, .. } => report.tokens += tokens,
+                EventKind::Finished {
+                    success,
+                    reason,
+                    attempts,
+                } => {
+                    report.success = *success;
+                    report.reason = reason.clone();
+                    report.attempts = *attempts;
+                }
+                _ => {}
+            }
+        }
+        report.states = states;
+        report
+    }
+
+    pub fn print(&self) {
+        info!(
+            "Report: {} after {} rewrites in {} ms, ~{} tokens",
+            if self.success { "succeeded" } else { "failed" },
+            self.attempts,
+            self.duration_ms,
+            self.tokens
+        );
+        for s in &self.states {
+            info!("  {:<12} {:>3}x {:>8} ms", s.state, s.entered, s.ms);
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, serde_json::to_string_pretty(self).unwrap())
+            .map_err(|e| format!("Cant write {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(elapsed_ms: u128, kind: EventKind) -> Event {
+        Event {
+            at_ms: 0,
+            elapsed_ms,
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_report_from_events() {
+        let entered = |state, attempt| EventKind::Entered { state, attempt };
+        let events = vec![
+            event(0, entered("generate", 0)),
+            event(
+                90,
+                EventKind::Requested {
+                    prompt: "generate",
+                    attempt: 0,
+                    candidate: None,
+                    tokens: 250,
+                    request: String::new(),
+                    response: String::new(),
+                },
+            ),
+            event(100, entered("build", 0)),
+            event(400, entered("rewrite", 0)),
+            event(500, entered("build", 1)),
+            event(600, entered("failed", 1)),
+            event(
+                600,
+                EventKind::Finished {
+                    success: false,
+                    reason: Some("too many attempts".to_string()),
+                    attempts: 1,
+                },
+            ),
+        ];
+        let report = Report::from_events(&events);
+        assert!(!report.success);
+        assert_eq!(report.attempts, 1);
+        assert_eq!(report.tokens, 250);
+        assert_eq!(report.duration_ms, 600);
+        assert_eq!(
+            report.states[1],
+            StateTime {
+                state: "build",
+                entered: 2,
+                ms: 400
+            }
+        );
+        assert_eq!(report.states.len(), 4);
+        assert_eq!(report.reason.as_deref(), Some("too many attempts"));
+        let json = serde_json::to_string(&events[0]).unwrap();
+        assert!(json.contains("\"event\":\"entered\",\"state\":\"generate\""));
+    }
+}
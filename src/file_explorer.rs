@@ -1,33 +1,355 @@
-pub fn explore_files(
-    path: &str,
-    include_ext: &Vec<String>,
-    exclude_path: &Vec<String>,
-) -> Vec<String> {
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::GitignoreBuilder;
+use ignore::{Match, WalkBuilder};
+use std::io::Read;
+use std::path::Path;
+
+use crate::VERBOSE;
+
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+// Same heuristic as git: a NUL byte in the first 8000 bytes means binary
+const BINARY_CHECK_LEN: usize = 8000;
+
+#[derive(Debug, Clone)]
+pub struct ExploreOptions {
+    pub include_ext: Vec<String>,
+    // Directory names skipped anywhere in the tree, e.g. "target" or "node_modules"
+    pub exclude_dirs: Vec<String>,
+    // Globs relative to the explored folder, e.g. "src/**" or "**/*_test.rs"
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub max_file_size: u64,
+    pub follow_links: bool,
+}
+
+impl Default for ExploreOptions {
+    fn default() -> Self {
+        Self {
+            include_ext: vec![],
+            exclude_dirs: vec![],
+            include_globs: vec![],
+            exclude_globs: vec![],
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            follow_links: false,
+        }
+    }
+}
+
+// Walks `path` honouring .gitignore/.ignore files and skipping hidden, binary and
+// oversized files. Unreadable entries and symlink loops are reported and skipped.
+pub fn explore_files(path: &str, options: &ExploreOptions) -> Vec<String> {
+    let include = glob_filter(&options.include_globs);
+    let exclude = glob_filter(&options.exclude_globs);
+    let exclude_dirs = options.exclude_dirs.clone();
+    let root = Path::new(path).to_path_buf();
+
+    let walker = WalkBuilder::new(path)
+        .hidden(true)
+        .git_ignore(true)
+        .git_global(true)
+        .git_exclude(true)
+        .ignore(true)
+        .require_git(false)
+        .follow_links(options.follow_links)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !(is_dir
+                && entry.depth() > 0
+                && exclude_dirs
+                    .iter()
+                    .any(|dir| entry.file_name().to_str() == Some(dir.as_str())))
+        })
+        .build();
+
     let mut files = Vec::new();
-    let paths = std::fs::read_dir(path).unwrap();
-    for path in paths {
-        let path = path.unwrap().path();
-        if path.is_dir() {
-            files.append(&mut explore_files(
-                path.to_str().unwrap(),
-                include_ext,
-                exclude_path,
-            ));
-        } else {
-            let path = path.to_str().unwrap();
-            if include_ext.iter().any(|ext| {
-                let file_ext = format!(".{}", ext);
-                path.ends_with(file_ext.as_str())
-            }) {
-                if exclude_path.iter().any(|exclude| {
-                    // TODO: generate full path not only folder name
-                    path.contains(exclude)
-                }) {
-                    continue;
-                }
-                files.push(path.to_string());
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                info!("Warning: skip {}", e);
+                continue;
             }
+        };
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let file_path = entry.path();
+        let relative = file_path.strip_prefix(&root).unwrap_or(file_path);
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if is_listed(file_path, relative, size, options, &include, &exclude) {
+            files.push(file_path.to_string_lossy().to_string());
+        }
+    }
+    files.sort();
+    files
+}
+
+// The files among `changed` that explore_files(path, options) would list, checked one by
+// one instead of walking the tree. Ignore rules come from the .gitignore and .ignore files
+// of the folders from `path` down to each file. Deleted files are not listed.
+pub fn filter_files(path: &str, changed: &[String], options: &ExploreOptions) -> Vec<String> {
+    let include = glob_filter(&options.include_globs);
+    let exclude = glob_filter(&options.exclude_globs);
+    let root = Path::new(path);
+    let mut files = Vec::new();
+    for file in changed {
+        let file_path = Path::new(file);
+        let metadata = if options.follow_links {
+            std::fs::metadata(file_path)
+        } else {
+            std::fs::symlink_metadata(file_path)
+        };
+        if !metadata.as_ref().is_ok_and(|m| m.is_file()) {
+            continue;
+        }
+        let Ok(relative) = file_path.strip_prefix(root) else {
+            continue;
+        };
+        let names: Vec<&str> = relative
+            .components()
+            .filter_map(|c| c.as_os_str().to_str())
+            .collect();
+        let Some((_, folders)) = names.split_last() else {
+            continue;
+        };
+        let excluded_dir = folders
+            .iter()
+            .any(|name| options.exclude_dirs.iter().any(|dir| dir == name));
+        if excluded_dir || names.iter().any(|name| name.starts_with('.')) {
+            continue;
+        }
+        if is_ignored(root, relative) {
+            continue;
+        }
+        let size = metadata.map(|m| m.len()).unwrap_or(0);
+        if is_listed(file_path, relative, size, options, &include, &exclude) {
+            files.push(file.clone());
         }
     }
+    files.sort();
     files
 }
+
+// The checks of a file itself: extension, globs, size and content
+fn is_listed(
+    file_path: &Path,
+    relative: &Path,
+    size: u64,
+    options: &ExploreOptions,
+    include: &Option<GlobSet>,
+    exclude: &Option<GlobSet>,
+) -> bool {
+    let file_name = file_path.to_string_lossy();
+    if !options.include_ext.iter().any(|ext| {
+        let file_ext = format!(".{}", ext);
+        file_name.ends_with(file_ext.as_str())
+    }) {
+        return false;
+    }
+    if let Some(include) = include {
+        if !include.is_match(relative) {
+            return false;
+        }
+    }
+    if let Some(exclude) = exclude {
+        if exclude.is_match(relative) {
+            return false;
+        }
+    }
+    if size > options.max_file_size {
+        if *VERBOSE.lock().unwrap() {
+            info!("Skip {}: {} bytes is over the size limit", file_name, size);
+        }
+        return false;
+    }
+    if is_binary(file_path) {
+        if *VERBOSE.lock().unwrap() {
+            info!("Skip {}: binary file", file_name);
+        }
+        return false;
+    }
+    true
+}
+
+// Like the walk, the ignore files of the deepest folder with a matching rule decide
+fn is_ignored(root: &Path, relative: &Path) -> bool {
+    let file_path = root.join(relative);
+    let mut folders = vec![root.to_path_buf()];
+    for folder in relative.parent().into_iter().flat_map(|p| p.components()) {
+        folders.push(folders.last().unwrap().join(folder));
+    }
+    for folder in folders.iter().rev() {
+        let mut builder = GitignoreBuilder::new(folder);
+        for name in [".gitignore", ".ignore"] {
+            let ignore_file = folder.join(name);
+            if ignore_file.is_file() {
+                builder.add(ignore_file);
+            }
+        }
+        let Ok(matcher) = builder.build() else {
+            continue;
+        };
+        match matcher.matched_path_or_any_parents(&file_path, false) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
+
+pub fn build_glob_set(globs: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob = Glob::new(glob).map_err(|e| format!("Invalid glob {}: {}", glob, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| format!("Invalid globs: {}", e))
+}
+
+// None when there are no globs. main validates --include and --exclude with
+// build_glob_set, so an invalid glob is only reported here and not applied
+fn glob_filter(globs: &[String]) -> Option<GlobSet> {
+    if globs.is_empty() {
+        return None;
+    }
+    build_glob_set(globs)
+        .inspect_err(|e| info!("Warning: {}", e))
+        .ok()
+}
+
+fn is_binary(path: &Path) -> bool {
+    let mut buf = vec![0u8; BINARY_CHECK_LEN];
+    let read = match std::fs::File::open(path).and_then(|mut f| f.read(&mut buf)) {
+        Ok(read) => read,
+        Err(_) => return true,
+    };
+    buf[..read].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustsn_explore_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        std::fs::create_dir_all(dir.join(".hidden")).unwrap();
+        std::fs::create_dir_all(dir.join("generated")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "pub fn a() {}").unwrap();
+        std::fs::write(dir.join("src/targets.rs"), "pub fn b() {}").unwrap();
+        std::fs::write(dir.join("src/lib_test.rs"), "fn t() {}").unwrap();
+        std::fs::write(dir.join("target/debug/build.rs"), "fn x() {}").unwrap();
+        std::fs::write(dir.join(".hidden/secret.rs"), "fn s() {}").unwrap();
+        std::fs::write(dir.join("generated/out.rs"), "fn g() {}").unwrap();
+        std::fs::write(dir.join("src/blob.rs"), [0u8, 1, 2, 3]).unwrap();
+        std::fs::write(dir.join("src/big.rs"), "x".repeat(2048)).unwrap();
+        std::fs::write(dir.join(".gitignore"), "generated/\n").unwrap();
+        dir
+    }
+
+    fn relative(dir: &Path, files: Vec<String>) -> Vec<String> {
+        files
+            .into_iter()
+            .map(|f| {
+                Path::new(&f)
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_explore_files_filters() {
+        let dir = fixture("filters");
+        let options = ExploreOptions {
+            include_ext: vec!["rs".to_string()],
+            exclude_dirs: vec!["target".to_string()],
+            max_file_size: 1024,
+            ..Default::default()
+        };
+        let files = relative(&dir, explore_files(dir.to_str().unwrap(), &options));
+        assert_eq!(
+            files,
+            vec!["src/lib.rs", "src/lib_test.rs", "src/targets.rs"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_explore_files_globs() {
+        let dir = fixture("globs");
+        let options = ExploreOptions {
+            include_ext: vec!["rs".to_string()],
+            include_globs: vec!["src/**".to_string()],
+            exclude_globs: vec!["**/*_test.rs".to_string()],
+            max_file_size: 1024,
+            ..Default::default()
+        };
+        let files = relative(&dir, explore_files(dir.to_str().unwrap(), &options));
+        assert_eq!(files, vec!["src/lib.rs", "src/targets.rs"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_glob_set() {
+        assert!(build_glob_set(&["src/**".to_string()]).is_ok());
+        let error = build_glob_set(&["src/[".to_string()]).unwrap_err();
+        assert!(error.starts_with("Invalid glob src/["));
+    }
+
+    #[test]
+    fn test_filter_files() {
+        let dir = fixture("filter");
+        std::fs::write(dir.join("src/.gitignore"), "lib_test.rs\n").unwrap();
+        let options = ExploreOptions {
+            include_ext: vec!["rs".to_string()],
+            exclude_dirs: vec!["target".to_string()],
+            max_file_size: 1024,
+            ..Default::default()
+        };
+        let changed: Vec<String> = [
+            "src/lib.rs",
+            "src/lib_test.rs",
+            "src/big.rs",
+            "src/blob.rs",
+            "src/deleted.rs",
+            "target/debug/build.rs",
+            ".hidden/secret.rs",
+            "generated/out.rs",
+        ]
+        .iter()
+        .map(|f| dir.join(f).to_string_lossy().to_string())
+        .collect();
+        let path = dir.to_str().unwrap();
+        let files = relative(&dir, filter_files(path, &changed, &options));
+        assert_eq!(files, vec!["src/lib.rs"]);
+        let walked = explore_files(path, &options);
+        assert_eq!(filter_files(path, &walked, &options), walked);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_explore_files_symlink_loop() {
+        let dir = fixture("symlink");
+        std::os::unix::fs::symlink(&dir, dir.join("src/loop")).unwrap();
+        let options = ExploreOptions {
+            include_ext: vec!["rs".to_string()],
+            exclude_dirs: vec!["target".to_string()],
+            max_file_size: 1024,
+            follow_links: true,
+            ..Default::default()
+        };
+        let files = relative(&dir, explore_files(dir.to_str().unwrap(), &options));
+        assert_eq!(
+            files,
+            vec!["src/lib.rs", "src/lib_test.rs", "src/targets.rs"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::llm_response::{LLMResponse, Project};
+use crate::toolchain::{write_files, Toolchain, SANDBOX_DIR};
+use crate::utils::estimate_tokens;
+use crate::Lang;
+
+// Budget of the other files of the target folder shown to the LLM
+const NEARBY_TOKENS: usize = 3000;
+// Never copied into the sandbox, besides the build output folders of the language
+const SKIPPED_DIRS: [&str; 3] = [".git", ".rustsn", SANDBOX_DIR];
+// Words before the name of a definition in the languages of generate
+const DEFINITION_KEYWORDS: [&str; 22] = [
+    "fn",
+    "struct",
+    "enum",
+    "trait",
+    "mod",
+    "const",
+    "static",
+    "type",
+    "union",
+    "def",
+    "class",
+    "function",
+    "interface",
+    "object",
+    "func",
+    "protocol",
+    "extension",
+    "namespace",
+    "record",
+    "val",
+    "var",
+    "let",
+];
+
+// "generate function --into FILE": the function and its tests are written into FILE of an
+// existing project. The project is copied to the sandbox with the edits on top, and the
+// edits reach the project only once it builds and its tests pass.
+#[derive(Debug)]
+pub struct IntoTarget {
+    // Folder of the manifest, e.g. the crate with the Cargo.toml
+    root: PathBuf,
+    // The target file relative to `root`
+    file: String,
+    manifest: &'static str,
+}
+
+impl IntoTarget {
+    pub fn new(file: &str, toolchain: &dyn Toolchain) -> Result<IntoTarget, String> {
+        let [manifest, _, _] = toolchain.function_files();
+        let path = Path::new(file);
+        let folder = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let folder = std::fs::canonicalize(folder)
+            .map_err(|e| format!("Cant open the folder of {}: {}", file, e))?;
+        let Some(root) = folder.ancestors().find(|dir| dir.join(manifest).is_file()) else {
+            return Err(format!(
+                "No {} found in the folders above {}",
+                manifest, file
+            ));
+        };
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("{} is not a file", file))?;
+        let relative = folder.join(file_name);
+        let relative = relative.strip_prefix(root).unwrap();
+        Ok(IntoTarget {
+            root: root.to_path_buf(),
+            file: relative.to_string_lossy().replace('\\', "/"),
+            manifest,
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.root.join(&self.file)
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    // Current content of a file of the project, empty for a new file
+    fn read(&self, file: &str) -> String {
+        std::fs::read_to_string(self.root.join(file)).unwrap_or_default()
+    }
+
+    // Parameters of the "generate" prompt in prompt/into.txt
+    pub fn generate_params(&self, lang: &Lang, question: &str) -> Vec<String> {
+        vec![
+            lang.to_string(),
+            self.manifest.to_string(),
+            self.read(self.manifest),
+            self.file.clone(),
+            self.read(&self.file),
+            self.nearby_code(),
+            question.to_string(),
+        ]
+    }
+
+    // Parameters of the "rewrite" prompt in prompt/into.txt
+    pub fn rewrite_params(
+        &self,
+        lang: &Lang,
+        project: &Project,
+        build_output: &str,
+        test_output: &str,
+        question: &str,
+    ) -> Vec<String> {
+        let edited = |file: &str| match project.files.iter().find(|f| f.path == file) {
+            Some(f) => f.content.clone(),
+            None => self.read(file),
+        };
+        vec![
+            lang.to_string(),
+            self.manifest.to_string(),
+            edited(self.manifest),
+            self.file.clone(),
+            edited(&self.file),
+            project.build_command.clone(),
+            build_output.to_string(),
+            project.test_command.clone(),
+            test_output.to_string(),
+            question.to_string(),
+        ]
+    }
+
+    // Only the target file and the manifest may be edited, anything else in the answer is dropped
+    pub fn parse(&self, response: &str, lang: Lang) -> Project {
+        let mut project = LLMResponse::parse_files(response, lang);
+        project
+            .files
+            .retain(|f| f.path == self.file || f.path == self.manifest);
+        self.set_base(&mut project);
+        project
+    }
+
+    // The edits are laid over a copy of the project
+    pub fn set_base(&self, project: &mut Project) {
+        project.base = Some(self.root.to_string_lossy().to_string());
+    }
+
+    // Top-level definitions of the target file the answer dropped, e.g. "fn old"
+    pub fn missing_definitions(&self, project: &Project) -> Vec<String> {
+        let Some(edited) = project.files.iter().find(|f| f.path == self.file) else {
+            return Vec::new();
+        };
+        let kept = definitions(&edited.content);
+        definitions(&self.read(&self.file))
+            .into_iter()
+            .filter(|d| !kept.contains(d))
+            .collect()
+    }
+
+    // Lays the edits of `project` over the copy of the project in `sandbox`, made once per
+    // run by toolchain::create_project: the files an earlier attempt may have edited are
+    // restored first, the rest of the copy and its build output are kept
+    pub fn update_sandbox(&self, project: &Project, sandbox: &Path) -> Result<(), String> {
+        for file in [self.manifest, self.file.as_str()] {
+            let (from, to) = (self.root.join(file), sandbox.join(file));
+            let restore = if from.is_file() {
+                std::fs::copy(&from, &to).map(|_| ())
+            } else if to.exists() {
+                std::fs::remove_file(&to)
+            } else {
+                Ok(())
+            };
+            restore.map_err(|e| format!("Cant restore {}: {}", to.display(), e))?;
+        }
+        write_files(sandbox, &project.files)?;
+        Ok(())
+    }
+
+    // Writes the edited files to the project, returns their paths
+    pub fn apply(&self, project: &Project) -> Result<Vec<String>, String> {
+        write_files(&self.root, &project.files)
+    }
+
+    // Other files of the target folder with the same extension, in name order
+    fn nearby_code(&self) -> String {
+        let target = self.root.join(&self.file);
+        let (Some(folder), Some(extension)) = (target.parent(), target.extension()) else {
+            return String::new();
+        };
+        let Ok(entries) = std::fs::read_dir(folder) else {
+            return String::new();
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p != &target && p.extension() == Some(extension))
+            .collect();
+        paths.sort();
+        let mut budget = NEARBY_TOKENS;
+        let mut snippets = Vec::new();
+        for path in paths {
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+            let snippet = format!(
+                "**{}**\n```\n{}\n```\n",
+                relative.display(),
+                content.trim_end()
+            );
+            let tokens = estimate_tokens(&snippet);
+            if tokens > budget {
+                continue;
+            }
+            budget -= tokens;
+            snippets.push(snippet);
+        }
+        snippets.join("\n")
+    }
+}
+
+// "keyword name" of every unindented line defining something, in any of the languages
+fn definitions(code: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    for line in code.lines() {
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let words: Vec<&str> = line
+            .split(|c: char| c.is_whitespace() || "(<{:=;,".contains(c))
+            .filter(|w| !w.is_empty())
+            .collect();
+        let Some(at) = words.iter().position(|w| DEFINITION_KEYWORDS.contains(w)) else {
+            continue;
+        };
+        let Some(name) = words.get(at + 1) else {
+            continue;
+        };
+        if name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+        {
+            found.push(format!("{} {}", words[at], name));
+        }
+    }
+    found
+}
+
+// Files of the project to copy, without ignored files and build output
+fn base_files(base: &Path, lang: &Lang) -> Vec<PathBuf> {
+    let excluded: Vec<&str> = SKIPPED_DIRS
+        .iter()
+        .chain(lang.excluded_dirs())
+        .copied()
+        .collect();
+    let walker = WalkBuilder::new(base)
+        // .env and .cargo/config.toml can matter to the build
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !(is_dir
+                && entry.depth() > 0
+                && excluded
+                    .iter()
+                    .any(|dir| entry.file_name().to_str() == Some(dir)))
+        })
+        .build();
+    let mut files: Vec<PathBuf> = walker
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| entry.path().strip_prefix(base).ok().map(Path::to_path_buf))
+        .collect();
+    files.sort();
+    files
+}
+
+// Copies the project to `to`, the sandbox the edits are laid over
+pub fn copy_base(base: &Path, lang: &Lang, to: &Path) -> Result<(), String> {
+    for file in base_files(base, lang) {
+        let target = to.join(&file);
+        std::fs::create_dir_all(target.parent().unwrap())
+            .and_then(|_| std::fs::copy(base.join(&file), &target))
+            .map_err(|e| format!("Cant copy {}: {}", file.display(), e))?;
+    }
+    Ok(())
+}
+
+// Changes when a file of the project changes, so cached build results of an older
+// version of the project are not reused
+pub fn fingerprint(base: &Path, lang: &Lang) -> String {
+    base_files(base, lang)
+        .iter()
+        .map(|file| {
+            let modified = std::fs::metadata(base.join(file))
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            format!("{} {}\n", file.display(), modified)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toolchain::RustToolchain;
+
+    #[test]
+    fn test_into_target() {
+        let dir = std::env::temp_dir().join("rustsn_test_into_target");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "pub mod utils;\n").unwrap();
+        std::fs::write(dir.join("src/utils.rs"), "pub fn old() {}\n").unwrap();
+        std::fs::write(dir.join("target/debug/out"), "binary").unwrap();
+
+        let file = dir.join("src/utils.rs");
+        let into = IntoTarget::new(file.to_str().unwrap(), &RustToolchain).unwrap();
+        assert_eq!(into.file, "src/utils.rs");
+        assert_eq!(into.root, std::fs::canonicalize(&dir).unwrap());
+        let params = into.generate_params(&Lang::Rust, "add two numbers");
+        assert_eq!(params[4], "pub fn old() {}\n");
+        assert!(params[5].contains("**src/lib.rs**"));
+
+        let response = "**src/utils.rs**\n```rust\npub fn old() {}\npub fn add() {}\n```\n\n**src/main.rs**\n```rust\nfn main() {}\n```\n\n**Test**\n```bash\ncargo test\n```\n";
+        let project = into.parse(response, Lang::Rust);
+        assert_eq!(project.files.len(), 1);
+        assert!(into.missing_definitions(&project).is_empty());
+        let dropped = into.parse(
+            "**src/utils.rs**\n```rust\npub fn add() {}\n```\n",
+            Lang::Rust,
+        );
+        assert_eq!(into.missing_definitions(&dropped), vec!["fn old"]);
+
+        let sandbox = dir.join("sandbox");
+        crate::toolchain::create_project(&dropped, &sandbox).unwrap();
+        std::fs::create_dir_all(sandbox.join("target")).unwrap();
+        std::fs::write(sandbox.join("target/out"), "build output").unwrap();
+        into.update_sandbox(&project, &sandbox).unwrap();
+        assert!(sandbox.join("target/out").exists());
+        assert!(std::fs::read_to_string(sandbox.join("src/utils.rs"))
+            .unwrap()
+            .contains("pub fn old() {}"));
+        std::fs::remove_dir_all(&sandbox).unwrap();
+
+        into.apply(&project).unwrap();
+        assert!(std::fs::read_to_string(dir.join("src/utils.rs"))
+            .unwrap()
+            .contains("pub fn add() {}"));
+        assert_eq!(
+            base_files(&into.root, &Lang::Rust),
+            vec![
+                PathBuf::from("Cargo.toml"),
+                PathBuf::from("src/lib.rs"),
+                PathBuf::from("src/utils.rs")
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
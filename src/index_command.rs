@@ -0,0 +1,766 @@
+use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::notify::RecursiveMode;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::ask_command::{AskError, EXIT_INDEX, EXIT_LLM, EXIT_NO_FILES};
+use crate::cache::Cache;
+use crate::documents::{self, DOC_EXTENSIONS};
+use crate::file_explorer::{self, ExploreOptions};
+use crate::llm_api::LLMApi;
+use crate::llm_prompt::Prompt;
+use crate::project_index::{IndexSettings, ProjectIndex};
+use crate::utils::content_hash;
+use crate::{Lang, VERBOSE};
+
+// Every language the index supports, in the order used by --lang=auto
+pub const INDEX_LANGS: [Lang; 10] = [
+    Lang::Rust,
+    Lang::Java,
+    Lang::JavaScript,
+    Lang::TypeScript,
+    Lang::Scala,
+    Lang::Python,
+    Lang::Kotlin,
+    Lang::Php,
+    Lang::Swift,
+    Lang::CSharp,
+];
+const EXPLAIN_PROMPT: &str = "Explain how this code works and what it does:";
+// Changes closer than this are indexed together, e.g. a "save all" in the editor
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub struct IndexOptions {
+    pub langs: Vec<Lang>,
+    pub explore: ExploreOptions,
+    // Index each file together with an LLM explanation of it
+    pub summarize: bool,
+    // false builds a keyword-only index, usable by "ask --retrieval lexical"
+    pub embeddings: bool,
+    // Also index documentation and config files in chunks
+    pub docs: bool,
+}
+
+impl IndexOptions {
+    fn settings(&self) -> IndexSettings {
+        IndexSettings {
+            langs: self.langs.iter().map(|l| l.to_string()).collect(),
+            include_globs: self.explore.include_globs.clone(),
+            exclude_globs: self.explore.exclude_globs.clone(),
+            max_file_size: self.explore.max_file_size,
+            follow_links: self.explore.follow_links,
+            summarize: self.summarize,
+            embeddings: self.embeddings,
+            docs: self.docs,
+        }
+    }
+
+    fn from_settings(settings: &IndexSettings) -> IndexOptions {
+        IndexOptions {
+            langs: settings
+                .langs
+                .iter()
+                .filter_map(|l| l.parse().ok())
+                .collect(),
+            explore: ExploreOptions {
+                include_globs: settings.include_globs.clone(),
+                exclude_globs: settings.exclude_globs.clone(),
+                max_file_size: settings.max_file_size,
+                follow_links: settings.follow_links,
+                ..Default::default()
+            },
+            summarize: settings.summarize,
+            embeddings: settings.embeddings,
+            docs: settings.docs,
+        }
+    }
+}
+
+pub fn detect_languages(path: &str, explore_options: &ExploreOptions) -> Vec<Lang> {
+    let all_options = ExploreOptions {
+        include_ext: INDEX_LANGS
+            .iter()
+            .flat_map(|l| l.source_extensions())
+            .map(|ext| ext.to_string())
+            .collect(),
+        ..explore_options.clone()
+    };
+    let files = file_explorer::explore_files(path, &all_options);
+    INDEX_LANGS
+        .iter()
+        .filter(|l| {
+            files.iter().any(|f| {
+                l.source_extensions()
+                    .iter()
+                    .any(|ext| f.ends_with(&format!(".{}", ext)))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+// Each language is explored with its own extensions and excluded folders, so the
+// "bin" folder of a C# project does not hide the src/bin of a Rust crate next to it.
+fn explore_index_files(path: &str, options: &IndexOptions) -> Vec<String> {
+    let mut files: Vec<String> = explore_options(options)
+        .iter()
+        .flat_map(|explore| file_explorer::explore_files(path, explore))
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+// The files among `touched` that explore_index_files would list, without a walk
+fn filter_index_files(path: &str, touched: &[String], options: &IndexOptions) -> Vec<String> {
+    let mut files: Vec<String> = explore_options(options)
+        .iter()
+        .flat_map(|explore| file_explorer::filter_files(path, touched, explore))
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+// The explore options of each language, and of the documents with --docs
+fn explore_options(options: &IndexOptions) -> Vec<ExploreOptions> {
+    let mut explore = Vec::new();
+    for lang in &options.langs {
+        let lang_options = ExploreOptions {
+            include_ext: lang
+                .source_extensions()
+                .iter()
+                .chain(lang.config_extensions())
+                .map(|ext| ext.to_string())
+                .collect(),
+            exclude_dirs: lang.excluded_dirs().iter().map(|d| d.to_string()).collect(),
+            ..options.explore.clone()
+        };
+        explore.push(lang_options);
+    }
+    if options.docs {
+        // Documents are skipped in the build folders of every language, e.g. the
+        // README of each package in node_modules
+        let doc_options = ExploreOptions {
+            include_ext: DOC_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            exclude_dirs: options
+                .langs
+                .iter()
+                .flat_map(|l| l.excluded_dirs())
+                .map(|d| d.to_string())
+                .collect(),
+            ..options.explore.clone()
+        };
+        explore.push(doc_options);
+    }
+    explore
+}
+
+// Text that is embedded and keyword-indexed for a file: its code and, with
+// --summarize, the LLM explanation, which matches questions asked in plain words
+fn index_document(file: &str, content: &str, summary: Option<&str>) -> String {
+    match summary {
+        Some(summary) => format!("# {}\r\n{}\r\n{}", file, content, summary),
+        None => format!("# {}\r\n{}", file, content),
+    }
+}
+
+fn summarize_file(
+    file: &str,
+    content: &str,
+    index: &mut ProjectIndex,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<String, AskError> {
+    let hash = content_hash(content);
+    if let Some(summary) = index.summary(file, hash) {
+        return Ok(summary.to_string());
+    }
+    let prompt_template = format!(
+        "{}\r\n{}",
+        index_document(file, content, None),
+        EXPLAIN_PROMPT
+    );
+    let summary = llm
+        .try_request(&prompt_template, &Vec::new(), cache, prompt)
+        .map_err(|e| AskError::new(EXIT_LLM, format!("Cant summarize {}: {}", file, e)))?;
+    index.set_summary(file, hash, summary.clone());
+    Ok(summary)
+}
+
+// Builds the index of `path` or brings it up to date: only new and changed files
+// are summarized and embedded again, deleted files are removed.
+pub fn build_index(
+    path: &str,
+    options: &IndexOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<ProjectIndex, AskError> {
+    let files = explore_index_files(path, options);
+    if files.is_empty() {
+        return Err(AskError::new(
+            EXIT_NO_FILES,
+            format!("No source files to index in {}", path),
+        ));
+    }
+    let indexed: HashSet<&str> = files.iter().map(|f| f.as_str()).collect();
+    let mut index = ProjectIndex::load(path);
+    let removed = index
+        .files()
+        .filter(|f| !indexed.contains(f.as_str()))
+        .count();
+    index.set_settings(options.settings());
+    if options.embeddings {
+        index.set_embedding_model(&llm.model_names().1);
+    } else {
+        index.set_embedding_model("");
+    }
+    let mut updated = 0;
+    for file in &files {
+        if let Err(e) = index_file(file, &mut index, options, llm, cache, prompt, &mut updated) {
+            // Keep the work done so far for the next run
+            index.save();
+            return Err(e);
+        }
+    }
+    index.retain(|id| indexed.contains(id));
+    index.refresh_ann();
+    index.save();
+    info!(
+        "Indexed {} files: {} updated, {} removed",
+        files.len(),
+        updated,
+        removed
+    );
+    Ok(index)
+}
+
+fn index_file(
+    file: &str,
+    index: &mut ProjectIndex,
+    options: &IndexOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+    updated: &mut usize,
+) -> Result<(), AskError> {
+    let content_file = match std::fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) => {
+            info!("Warning: cant read {}: {}", file, e);
+            return Ok(());
+        }
+    };
+    let hash = content_hash(&content_file);
+    if index.is_fresh(file, hash) {
+        return Ok(());
+    }
+    info!("File: {:?}", file);
+    *updated += 1;
+    if documents::is_document(file) {
+        return index_chunks(file, &content_file, hash, index, options, llm, cache);
+    }
+    let summary = if options.summarize {
+        let summary = summarize_file(file, &content_file, index, llm, cache, prompt)?;
+        if *VERBOSE.lock().unwrap() {
+            info!("Summary of {}:\r\n{}", file, summary);
+        }
+        Some(summary)
+    } else {
+        None
+    };
+    let content = index_document(file, &content_file, summary.as_deref());
+    index.insert_text(file, &content);
+    index.insert_symbols(file, &content_file);
+    index.set_hash(file, hash);
+    if !options.embeddings {
+        return Ok(());
+    }
+    let emb = llm.emb(&content, cache, &content);
+    if emb.is_empty() {
+        info!("Warning: empty embedding for {}, skip it", file);
+        return Ok(());
+    }
+    index
+        .insert(file, emb)
+        .map_err(|e| AskError::new(EXIT_INDEX, e))
+}
+
+// Documents are indexed chunk by chunk and never summarized, they are prose already
+fn index_chunks(
+    file: &str,
+    content_file: &str,
+    hash: u64,
+    index: &mut ProjectIndex,
+    options: &IndexOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+) -> Result<(), AskError> {
+    let chunks = documents::chunk_document(file, content_file);
+    let ids: Vec<String> = chunks
+        .iter()
+        .map(|c| documents::chunk_id(file, c.start, c.end))
+        .collect();
+    index.set_chunks(file, ids.clone());
+    index.set_hash(file, hash);
+    for (chunk, id) in chunks.iter().zip(&ids) {
+        let content = index_document(id, &chunk.text, None);
+        index.insert_text(id, &content);
+        if !options.embeddings {
+            continue;
+        }
+        let emb = llm.emb(&content, cache, &content);
+        if emb.is_empty() {
+            info!("Warning: empty embedding for {}, skip it", id);
+            continue;
+        }
+        index
+            .insert(id, emb)
+            .map_err(|e| AskError::new(EXIT_INDEX, e))?;
+    }
+    Ok(())
+}
+
+// New, changed and deleted files compared to what is on disk now
+struct Staleness {
+    new: usize,
+    changed: usize,
+    deleted: usize,
+}
+
+fn staleness(index: &ProjectIndex, files: &[String]) -> Staleness {
+    let mut stale = Staleness {
+        new: 0,
+        changed: 0,
+        deleted: 0,
+    };
+    for file in files {
+        match index.hash(file) {
+            None => stale.new += 1,
+            Some(hash) => {
+                let current = std::fs::read_to_string(file).map(|c| content_hash(&c));
+                if current.ok() != Some(hash) {
+                    stale.changed += 1;
+                }
+            }
+        }
+    }
+    let on_disk: HashSet<&str> = files.iter().map(|f| f.as_str()).collect();
+    stale.deleted = index
+        .files()
+        .filter(|f| !on_disk.contains(f.as_str()))
+        .count();
+    stale
+}
+
+fn print_status(path: &str) -> Result<(), AskError> {
+    let index = ProjectIndex::load(path);
+    if index.is_empty() {
+        return Err(AskError::new(
+            EXIT_INDEX,
+            format!(
+                "No index for {}, build it with: rustsn index {}",
+                path, path
+            ),
+        ));
+    }
+    let options = IndexOptions::from_settings(index.settings());
+    let files = explore_index_files(path, &options);
+    let stale = staleness(&index, &files);
+    println!("Index: {}", index.path());
+    println!("Root: {}", index.root());
+    println!("Languages: {}", index.settings().langs.join(", "));
+    println!(
+        "Files: {} indexed, {} with embeddings, {} with summaries",
+        index.files().count(),
+        index.embedded_count(),
+        index.summary_count()
+    );
+    if index.settings().docs {
+        println!(
+            "Documents: {} files in {} chunks",
+            index.document_count(),
+            index.chunk_count()
+        );
+    }
+    println!(
+        "Stale: {} changed, {} new, {} deleted",
+        stale.changed, stale.new, stale.deleted
+    );
+    if index.embedding_model().is_empty() {
+        println!("Embedding model: none (keyword search only)");
+    } else {
+        println!(
+            "Embedding model: {} ({} dimensions)",
+            index.embedding_model(),
+            index.dimensions()
+        );
+    }
+    Ok(())
+}
+
+// Id of a changed file in the index: the path as the explorer reports it, i.e.
+// relative to the folder given on the command line rather than absolute
+fn index_id(root: &Path, path: &str, changed: &Path) -> Option<String> {
+    let relative = changed.strip_prefix(root).ok()?;
+    Some(Path::new(path).join(relative).to_string_lossy().to_string())
+}
+
+// Indexes the touched files again and drops the deleted ones. Returns false
+// when nothing changed, e.g. only ignored files were touched.
+fn update_index(
+    path: &str,
+    touched: &[String],
+    index: &mut ProjectIndex,
+    options: &IndexOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<bool, AskError> {
+    // A changed ignore file or a folder moved into the project can change the files
+    // anywhere below it, only then the whole tree is walked again
+    let walk = touched.iter().any(|f| {
+        let file = Path::new(f);
+        file.is_dir()
+            || file
+                .file_name()
+                .is_some_and(|n| n == ".gitignore" || n == ".ignore")
+    });
+    let files = if walk {
+        explore_index_files(path, options)
+    } else {
+        filter_index_files(path, touched, options)
+    };
+    let on_disk: HashSet<&str> = files.iter().map(|f| f.as_str()).collect();
+    let mut updated = 0;
+    for file in &files {
+        match index_file(file, index, options, llm, cache, prompt, &mut updated) {
+            // e.g. the summary timed out, the file stays stale and its next change retries it
+            Err(e) if e.code == EXIT_LLM => info!("Warning: {}", e.message),
+            result => result?,
+        }
+    }
+    // Touched files that are gone or no longer listed, and the files of a folder
+    // that was deleted or renamed as a whole
+    let gone = |file: &str| {
+        !on_disk.contains(file)
+            && (walk
+                || touched
+                    .iter()
+                    .any(|t| file == t || Path::new(file).starts_with(t)))
+    };
+    let removed = index.files().filter(|f| gone(f)).count();
+    if updated == 0 && removed == 0 {
+        return Ok(false);
+    }
+    index.retain(|id| !gone(id));
+    index.refresh_ann();
+    info!("Updated {} files, removed {}", updated, removed);
+    Ok(true)
+}
+
+// Keeps the index of `path` fresh until the process is stopped. "ask" loads
+// the index from disk, so it always sees the last saved state.
+fn watch_index(
+    path: &str,
+    options: &IndexOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<(), AskError> {
+    let mut index = build_index(path, options, llm, cache, prompt)?;
+    let root = std::fs::canonicalize(path)
+        .map_err(|e| AskError::new(EXIT_NO_FILES, format!("Cant watch {}: {}", path, e)))?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut debouncer = new_debouncer(WATCH_DEBOUNCE, tx)
+        .map_err(|e| AskError::new(1, format!("Cant watch {}: {}", path, e)))?;
+    debouncer
+        .watcher()
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| AskError::new(1, format!("Cant watch {}: {}", path, e)))?;
+    info!("Watching {} for changes, press Ctrl+C to stop", path);
+
+    for result in rx {
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                info!("Warning: watch error: {}", e);
+                continue;
+            }
+        };
+        let mut touched: Vec<String> = events
+            .iter()
+            .filter_map(|event| index_id(&root, path, &event.path))
+            .collect();
+        touched.sort();
+        touched.dedup();
+        if update_index(path, &touched, &mut index, options, llm, cache, prompt)? {
+            index.save();
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_index_command(
+    path: &str,
+    options: &IndexOptions,
+    status: bool,
+    watch: bool,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<(), AskError> {
+    if status {
+        return print_status(path);
+    }
+    if watch {
+        return watch_index(path, options, llm, cache, prompt);
+    }
+    let start = Instant::now();
+    build_index(path, options, llm, cache, prompt)?;
+    info!("Index is ready in {} ms", start.elapsed().as_millis());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_index::Retrieval;
+
+    // Bag of words embedding, enough to compare what the documents contain
+    fn toy_embedding(text: &str) -> Vec<f32> {
+        let mut emb = vec![0.0f32; 1024];
+        for term in crate::lexical_index::tokenize(text) {
+            emb[(content_hash(&term) % 1024) as usize] += 1.0;
+        }
+        let norm = emb.iter().map(|x| x * x).sum::<f32>().sqrt().max(1e-6);
+        emb.iter().map(|x| x / norm).collect()
+    }
+
+    fn summarize_fixture_hits(summarize: bool) -> usize {
+        let fixture: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string("./test_data/ask_summarize.json").unwrap(),
+        )
+        .unwrap();
+        let mut index = ProjectIndex::default();
+        for file in fixture["files"].as_array().unwrap() {
+            let path = file["path"].as_str().unwrap();
+            let summary = summarize.then(|| file["summary"].as_str().unwrap());
+            let document = index_document(path, file["code"].as_str().unwrap(), summary);
+            index.insert(path, toy_embedding(&document)).unwrap();
+        }
+        fixture["questions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|q| {
+                let question = q["question"].as_str().unwrap();
+                let emb = toy_embedding(question);
+                let found = index.search(question, Some(&emb), Retrieval::Semantic, 1, &|_| true);
+                found[0].0 == q["expected"].as_str().unwrap()
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_summaries_improve_semantic_retrieval() {
+        let raw = summarize_fixture_hits(false);
+        let summarized = summarize_fixture_hits(true);
+        assert_eq!(summarized, 4);
+        assert!(summarized > raw, "raw {} summarized {}", raw, summarized);
+    }
+
+    #[test]
+    fn test_update_index_after_changes() {
+        let dir = std::env::temp_dir().join("rustsn_index_update");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let path = dir.to_string_lossy().to_string();
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        std::fs::write(file("src/a.rs"), "fn alpha() {}").unwrap();
+        std::fs::write(file("src/b.rs"), "fn beta() {}").unwrap();
+
+        let options = IndexOptions {
+            langs: vec![Lang::Rust],
+            explore: ExploreOptions::default(),
+            summarize: false,
+            embeddings: false,
+            docs: false,
+        };
+        // No embeddings and summaries: the LLM is never called
+        let llm = LLMApi::new(crate::llm_api::ModelType::Ollama {
+            model: String::new(),
+            emb: String::new(),
+        });
+        let mut cache = Cache::default();
+        let prompt = Prompt::default();
+        let mut index = ProjectIndex::default();
+        let all = vec![file("src/a.rs"), file("src/b.rs")];
+        let update = |index: &mut ProjectIndex, touched: &[String], cache: &mut Cache| {
+            update_index(&path, touched, index, &options, &llm, cache, &prompt).unwrap()
+        };
+        assert!(update(&mut index, &all, &mut cache));
+        assert!(!update(&mut index, &all, &mut cache));
+
+        std::fs::write(file("src/a.rs"), "fn gamma() {}").unwrap();
+        std::fs::remove_file(file("src/b.rs")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not indexed").unwrap();
+        let touched = vec![file("src/a.rs"), file("src/b.rs"), file("notes.txt")];
+        assert!(update(&mut index, &touched, &mut cache));
+        assert_eq!(index.files().collect::<Vec<_>>(), vec![&file("src/a.rs")]);
+        let found = index.search("gamma", None, Retrieval::Lexical, 3, &|_| true);
+        assert_eq!(found[0].0, file("src/a.rs"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_index_documents() {
+        let dir = std::env::temp_dir().join("rustsn_index_docs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        let path = dir.to_string_lossy().to_string();
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        std::fs::write(file("lib.rs"), "fn alpha() {}").unwrap();
+        std::fs::write(
+            file("docs/adr.md"),
+            "# Decisions\n## Cache\nResponses are cached in bincode.\n## Docker\nBuilds run in a container.\n",
+        )
+        .unwrap();
+        std::fs::write(file("target/notes.md"), "# Cache").unwrap();
+
+        let mut options = IndexOptions {
+            langs: vec![Lang::Rust],
+            explore: ExploreOptions::default(),
+            summarize: false,
+            embeddings: false,
+            docs: true,
+        };
+        let files = explore_index_files(&path, &options);
+        assert_eq!(files, vec![file("docs/adr.md"), file("lib.rs")]);
+        let llm = LLMApi::new(crate::llm_api::ModelType::Ollama {
+            model: String::new(),
+            emb: String::new(),
+        });
+        let mut cache = Cache::default();
+        let mut index = ProjectIndex::default();
+        update_index(
+            &path,
+            &files,
+            &mut index,
+            &options,
+            &llm,
+            &mut cache,
+            &Prompt::default(),
+        )
+        .unwrap();
+        let found = index.search("bincode cached", None, Retrieval::Lexical, 1, &|_| true);
+        assert_eq!(found[0].0, format!("{}#L2-3", file("docs/adr.md")));
+        assert_eq!(index.chunk_count(), 3);
+
+        // Changed chunks replace the old ones, without --docs the documents are dropped
+        std::fs::write(
+            file("docs/adr.md"),
+            "# Decisions\nBuilds run in a container.\n",
+        )
+        .unwrap();
+        update_index(
+            &path,
+            &files,
+            &mut index,
+            &options,
+            &llm,
+            &mut cache,
+            &Prompt::default(),
+        )
+        .unwrap();
+        assert_eq!(index.id_count(), 2);
+        options.docs = false;
+        update_index(
+            &path,
+            &files,
+            &mut index,
+            &options,
+            &llm,
+            &mut cache,
+            &Prompt::default(),
+        )
+        .unwrap();
+        assert_eq!(index.id_count(), 1);
+        assert_eq!(index.files().collect::<Vec<_>>(), vec![&file("lib.rs")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_summarized_index_keeps_documents_fresh() {
+        let dir = std::env::temp_dir().join("rustsn_index_docs_summarize");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_string_lossy().to_string();
+        let adr = dir.join("adr.md").to_string_lossy().to_string();
+        std::fs::write(&adr, "# Cache\nResponses are cached in bincode.\n").unwrap();
+
+        let options = IndexOptions {
+            langs: vec![Lang::Rust],
+            explore: ExploreOptions::default(),
+            summarize: true,
+            embeddings: false,
+            docs: true,
+        };
+        let llm = LLMApi::new(crate::llm_api::ModelType::Ollama {
+            model: String::new(),
+            emb: String::new(),
+        });
+        let mut cache = Cache::default();
+        let prompt = Prompt::default();
+        let mut index = ProjectIndex::default();
+        index.set_settings(options.settings());
+        let files = vec![adr];
+        let mut update = || {
+            update_index(
+                &path, &files, &mut index, &options, &llm, &mut cache, &prompt,
+            )
+            .unwrap()
+        };
+        assert!(update());
+        assert!(!update());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_index_id() {
+        let root = Path::new("/home/user/project");
+        assert_eq!(
+            index_id(
+                root,
+                "../project",
+                Path::new("/home/user/project/src/main.rs")
+            ),
+            Some("../project/src/main.rs".to_string())
+        );
+        assert_eq!(index_id(root, ".", Path::new("/tmp/other.rs")), None);
+    }
+
+    #[test]
+    fn test_staleness() {
+        let dir = std::env::temp_dir().join("rustsn_index_staleness");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        std::fs::write(file("a.rs"), "fn a() {}").unwrap();
+        std::fs::write(file("b.rs"), "fn b() {}").unwrap();
+        std::fs::write(file("c.rs"), "fn c() {}").unwrap();
+
+        let mut index = ProjectIndex::default();
+        index.set_hash(&file("a.rs"), content_hash("fn a() {}"));
+        index.set_hash(&file("b.rs"), content_hash("fn b() { old }"));
+        index.set_hash(&file("gone.rs"), content_hash("fn gone() {}"));
+        let files = vec![file("a.rs"), file("b.rs"), file("c.rs")];
+        let stale = staleness(&index, &files);
+        assert_eq!((stale.changed, stale.new, stale.deleted), (1, 1, 1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
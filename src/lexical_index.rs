@@ -0,0 +1,200 @@
+use bincode::{Decode, Encode};
+use std::collections::HashMap;
+
+// Okapi BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone)]
+struct DocEntry {
+    len: u32,
+    terms: Vec<String>,
+}
+
+// BM25 keyword index over the same documents as the embedding index.
+// It finds exact identifiers like `parse_positions` that embeddings tend to blur.
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone)]
+pub struct LexicalIndex {
+    docs: HashMap<String, DocEntry>,
+    postings: HashMap<String, HashMap<String, u32>>,
+    total_len: u64,
+}
+
+impl LexicalIndex {
+    pub fn insert(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        let mut len = 0;
+        for term in tokenize(text) {
+            *frequencies.entry(term).or_insert(0) += 1;
+            len += 1;
+        }
+        let terms = frequencies.keys().cloned().collect();
+        for (term, tf) in frequencies {
+            self.postings
+                .entry(term)
+                .or_default()
+                .insert(id.to_string(), tf);
+        }
+        self.total_len += len as u64;
+        self.docs.insert(id.to_string(), DocEntry { len, terms });
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(entry) = self.docs.remove(id) else {
+            return;
+        };
+        self.total_len -= entry.len as u64;
+        for term in entry.terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &String> {
+        self.docs.keys()
+    }
+
+    // Only the ids `keep` accepts are ranked
+    pub fn search(&self, query: &str, k: usize, keep: &dyn Fn(&str) -> bool) -> Vec<(String, f32)> {
+        if self.docs.is_empty() {
+            return vec![];
+        }
+        let n = self.docs.len() as f32;
+        let avg_len = self.total_len as f32 / n;
+        let mut query_terms = tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for term in &query_terms {
+            let Some(posting) = self.postings.get(term) else {
+                continue;
+            };
+            let df = posting.len() as f32;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (id, tf) in posting.iter().filter(|(id, _)| keep(id)) {
+                let tf = *tf as f32;
+                let len = self.docs[id].len as f32;
+                let score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                *scores.entry(id.as_str()).or_insert(0.0) += score;
+            }
+        }
+
+        let mut result: Vec<(String, f32)> = scores
+            .into_iter()
+            .map(|(id, score)| (id.to_string(), score))
+            .collect();
+        result.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        result.truncate(k);
+        result
+    }
+}
+
+// Splits text into lowercase identifier terms. Compound identifiers are kept whole
+// and also split into their snake_case / camelCase parts, so a question about
+// `parse_positions` matches both the exact name and the words "parse" and "positions".
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        let word = word.trim_matches('_');
+        if word.len() < 2 {
+            continue;
+        }
+        let parts = split_identifier(word);
+        if parts.len() > 1 {
+            for part in &parts {
+                if part.len() >= 2 {
+                    terms.push(part.clone());
+                }
+            }
+        }
+        terms.push(word.to_lowercase());
+    }
+    terms
+}
+
+fn split_identifier(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let chars: Vec<char> = word.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_' {
+            if !current.is_empty() {
+                parts.push(current.to_lowercase());
+                current.clear();
+            }
+            continue;
+        }
+        // "parseHTTPResponse" -> parse, http, response
+        let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+        let boundary = c.is_uppercase()
+            && i > 0
+            && (chars[i - 1].is_lowercase()
+                || chars[i - 1].is_ascii_digit()
+                || (chars[i - 1].is_uppercase() && next_is_lower));
+        if boundary && !current.is_empty() {
+            parts.push(current.to_lowercase());
+            current.clear();
+        }
+        current.push(*c);
+    }
+    if !current.is_empty() {
+        parts.push(current.to_lowercase());
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_splits_identifiers() {
+        let terms = tokenize("fn parse_positions(response: &str) -> HTTPResponse");
+        assert!(terms.contains(&"parse_positions".to_string()));
+        assert!(terms.contains(&"parse".to_string()));
+        assert!(terms.contains(&"positions".to_string()));
+        assert!(terms.contains(&"httpresponse".to_string()));
+        assert!(terms.contains(&"http".to_string()));
+        assert!(terms.contains(&"response".to_string()));
+    }
+
+    #[test]
+    fn test_search_finds_exact_identifier() {
+        let mut index = LexicalIndex::default();
+        index.insert(
+            "src/llm_response.rs",
+            "fn parse_positions(response: &str) -> Vec<(String, usize)> { }",
+        );
+        index.insert(
+            "src/cache.rs",
+            "pub fn get(&mut self, key: &str) -> Option<&String>",
+        );
+        index.insert(
+            "src/utils.rs",
+            "pub fn remove_comments(text: &str) -> String",
+        );
+
+        let result = index.search("how does `parse_positions` work", 3, &|_| true);
+        assert_eq!(result[0].0, "src/llm_response.rs");
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_and_reinsert() {
+        let mut index = LexicalIndex::default();
+        index.insert("a.rs", "fn alpha() {}");
+        index.insert("b.rs", "fn beta() {}");
+        index.insert("a.rs", "fn gamma() {}");
+        assert!(index.search("alpha", 3, &|_| true).is_empty());
+        assert_eq!(index.search("gamma", 3, &|_| true)[0].0, "a.rs");
+        index.remove("b.rs");
+        assert!(index.search("beta", 3, &|_| true).is_empty());
+        assert_eq!(index.total_len, 2);
+    }
+}
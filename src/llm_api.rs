@@ -13,8 +13,13 @@ use std::time::Duration;
 // ];
 const STOP_WORDS: &[&str] = &[];
 const MAX_TOKENS: i32 = 1000;
+const OPENAI_CHAT_MODEL: &str = "gpt-4o-2024-08-06";
+const OPENAI_EMB_MODEL: &str = "text-embedding-ada-002";
 pub struct LLMApi {
     model_type: ModelType,
+    // Replaces the embedding API of model_type, see --emb-model
+    #[cfg(feature = "local-embeddings")]
+    local_emb: Option<crate::local_embedding::LocalEmbedder>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -25,16 +30,62 @@ pub enum ModelType {
 
 impl LLMApi {
     pub fn new(model_type: ModelType) -> LLMApi {
-        LLMApi { model_type }
+        LLMApi {
+            model_type,
+            #[cfg(feature = "local-embeddings")]
+            local_emb: None,
+        }
+    }
+
+    #[cfg(feature = "local-embeddings")]
+    pub fn with_local_embeddings(self, embedder: crate::local_embedding::LocalEmbedder) -> LLMApi {
+        LLMApi {
+            local_emb: Some(embedder),
+            ..self
+        }
+    }
+
+    // Names of the completion and embedding models, reported by "ask --format json"
+    pub fn model_names(&self) -> (String, String) {
+        #[cfg(feature = "local-embeddings")]
+        if let Some(local) = &self.local_emb {
+            let (llm, _) = self.api_model_names();
+            return (llm, local.name().to_string());
+        }
+        self.api_model_names()
+    }
+
+    fn api_model_names(&self) -> (String, String) {
+        match &self.model_type {
+            ModelType::Ollama { model, emb } => (model.clone(), emb.clone()),
+            ModelType::OpenAI { .. } => (
+                OPENAI_CHAT_MODEL.to_string(),
+                OPENAI_EMB_MODEL.to_string(),
+            ),
+        }
+    }
+
+    // Network and API errors are returned to the caller
+    pub fn try_request(
+        &self,
+        prompt_template: &str,
+        params: &Vec<String>,
+        cache: &mut Cache,
+        prompt: &Prompt,
+    ) -> Result<String, String> {
+        self.try_request_seeded(prompt_template, params, cache, prompt, None)
     }
 
-    pub fn request(
+    // Same as try_request, another `seed` samples another answer to the same prompt,
+    // see "generate --candidates". None keeps the request and its cache key unchanged.
+    pub fn try_request_seeded(
         &self,
         prompt_template: &str,
         params: &Vec<String>,
         cache: &mut Cache,
         prompt: &Prompt,
-    ) -> String {
+        seed: Option<u64>,
+    ) -> Result<String, String> {
         let prompt = if params.len() > 0 {
             prompt.create(prompt_template, params)
         } else {
@@ -54,12 +105,13 @@ impl LLMApi {
                     options: OllamaOptions {
                         num_predict: MAX_TOKENS,
                         stop: stop.iter().map(|s| s.to_string()).collect(),
+                        seed,
                     },
                 };
 
                 let request_str = serde_json::to_string(&request).unwrap();
                 if *VERBOSE.lock().unwrap() {
-                    println!("Request: {}", request.prompt);
+                    info!("Request: {}", request.prompt);
                 }
 
                 let response_opt = cache.get(&request_str);
@@ -69,30 +121,35 @@ impl LLMApi {
                             .timeout(Duration::from_secs(60 * 10))
                             .build()
                             .unwrap();
-                        println!("Request to LLM in progress");
+                        info!("Request to LLM in progress");
 
-                        let response = client.post(OLLAMA_API).json(&request).send().unwrap();
-                        if !response.status().is_success() {
-                            let response_text = response.text().unwrap();
-                            println!("Response: {:?}", response_text);
-                            panic!("Failed to get response from LLM");
+                        let response = client
+                            .post(OLLAMA_API)
+                            .json(&request)
+                            .send()
+                            .map_err(|e| format!("Network error: {}", e))?;
+                        let status = response.status();
+                        let response_text = response
+                            .text()
+                            .map_err(|e| format!("Network error: {}", e))?;
+                        if !status.is_success() {
+                            return Err(format!("HTTP {}: {}", status, response_text));
                         }
-                        let response_text = response.text().unwrap();
-                        let response =
-                            serde_json::from_str::<OllamaResponse>(&response_text).unwrap();
+                        let response = serde_json::from_str::<OllamaResponse>(&response_text)
+                            .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
                         cache.set(request_str.clone(), response.response.clone());
                         response.response
                     }
                     Some(result) => {
-                        println!("LLM Request already cached");
+                        info!("LLM Request already cached");
                         result.to_string()
                     }
                 };
 
                 if *VERBOSE.lock().unwrap() {
-                    println!("Response: {}", response);
+                    info!("Response: {}", response);
                 }
-                response
+                Ok(response)
             }
             ModelType::OpenAI { api_key } => {
                 let messages = vec![ChatMessage {
@@ -101,22 +158,23 @@ impl LLMApi {
                 }];
 
                 let request = OpenAIChatRequest {
-                    model: "gpt-4o-2024-08-06".to_string(),
+                    model: OPENAI_CHAT_MODEL.to_string(),
                     messages,
                     max_tokens: MAX_TOKENS,
                     temperature: 0.7,
                     stop: Some(STOP_WORDS.iter().map(|s| s.to_string()).collect()),
+                    seed,
                 };
 
                 let request_str = serde_json::to_string(&request).unwrap();
                 if *VERBOSE.lock().unwrap() {
-                    println!("Request: {}", prompt);
+                    info!("Request: {}", prompt);
                 }
 
                 let response_opt = cache.get(&request_str);
                 let response = match response_opt {
                     None => {
-                        println!("Request to LLM in progress");
+                        info!("Request to LLM in progress");
                         let client = Client::builder()
                             .timeout(Duration::from_secs(60 * 5))
                             .build()
@@ -127,9 +185,9 @@ impl LLMApi {
                             .bearer_auth(api_key)
                             .json(&request)
                             .send()
-                            .unwrap()
+                            .map_err(|e| format!("Network error: {}", e))?
                             .json::<OpenAIChatResponse>()
-                            .unwrap();
+                            .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
 
                         // Extract the assistant's reply from the first choice
                         let openai_response = response
@@ -143,19 +201,23 @@ impl LLMApi {
                         openai_response
                     }
                     Some(result) => {
-                        println!("LLM Request already cached");
+                        info!("LLM Request already cached");
                         result.to_string()
                     }
                 };
 
                 if *VERBOSE.lock().unwrap() {
-                    println!("OpenAI Chat Response: {}", response);
+                    info!("OpenAI Chat Response: {}", response);
                 }
-                response
+                Ok(response)
             }
         }
     }
     pub fn emb(&self, content: &str, cache: &mut Cache, full_content: &str) -> Vec<f32> {
+        #[cfg(feature = "local-embeddings")]
+        if let Some(local) = &self.local_emb {
+            return local_emb(local, content, cache, full_content);
+        }
         match &self.model_type {
             ModelType::Ollama { emb, .. } => {
                 let request = OllamaEmbRequest {
@@ -167,7 +229,7 @@ impl LLMApi {
                 let response_opt = cache.get(&request_str);
                 let response = match response_opt {
                     None => {
-                        println!("Request to Ollama Embeddings API in progress");
+                        info!("Request to Ollama Embeddings API in progress");
                         let client = Client::builder()
                             .timeout(Duration::from_secs(60 * 10))
                             .build()
@@ -180,16 +242,27 @@ impl LLMApi {
                         //     .json::<OllamaEmbResponse>()
                         //     .unwrap();
                         //
-                        let response_str = client
+                        let response_str = match client
                             .post(OLLAMA_EMB)
                             .json(&request)
                             .send()
-                            .unwrap()
-                            .text()
-                            .unwrap();
-                        // println!("Response: {}", response_str);
+                            .and_then(|resp| resp.text())
+                        {
+                            Ok(text) => text,
+                            Err(e) => {
+                                eprintln!("Network error: {}", e);
+                                return vec![];
+                            }
+                        };
+                        // info!("Response: {}", response_str);
                         let response: OllamaEmbResponse =
-                            serde_json::from_str(&response_str).unwrap();
+                            match serde_json::from_str(&response_str) {
+                                Ok(json) => json,
+                                Err(e) => {
+                                    eprintln!("Failed to parse JSON response: {}", e);
+                                    return vec![];
+                                }
+                            };
                         cache.set(
                             request_str.clone(),
                             serde_json::to_string(&response.embedding).unwrap(),
@@ -197,7 +270,7 @@ impl LLMApi {
                         response.embedding
                     }
                     Some(result) => {
-                        println!("Embedding Request already cached");
+                        info!("Embedding Request already cached");
                         serde_json::from_str(&result).unwrap()
                     }
                 };
@@ -205,7 +278,7 @@ impl LLMApi {
             }
             ModelType::OpenAI { api_key } => {
                 let request = OpenAIEmbRequest {
-                    model: "text-embedding-ada-002".to_string(),
+                    model: OPENAI_EMB_MODEL.to_string(),
                     input: full_content.to_string(),
                 };
 
@@ -220,7 +293,7 @@ impl LLMApi {
                             .build()
                             .unwrap();
 
-                        println!("Request to OpenAI Embeddings API in progress");
+                        info!("Request to OpenAI Embeddings API in progress");
 
                         let api_response = match client
                             .post("https://api.openai.com/v1/embeddings")
@@ -250,13 +323,13 @@ impl LLMApi {
                         api_response.data[0].embedding.clone()
                     }
                     Some(result) => {
-                        println!("Embedding Request already cached");
+                        info!("Embedding Request already cached");
                         serde_json::from_str(&result).unwrap()
                     }
                 };
 
                 if *VERBOSE.lock().unwrap() {
-                    println!("OpenAI Embedding Response: {:?}", response);
+                    info!("OpenAI Embedding Response: {:?}", response);
                 }
                 response.to_vec()
             }
@@ -264,6 +337,32 @@ impl LLMApi {
     }
 }
 
+// Cached like the API embeddings, keyed by the model as well since local models are
+// easy to swap
+#[cfg(feature = "local-embeddings")]
+fn local_emb(
+    local: &crate::local_embedding::LocalEmbedder,
+    content: &str,
+    cache: &mut Cache,
+    full_content: &str,
+) -> Vec<f32> {
+    let request_str = format!("{} {}", local.name(), content);
+    if let Some(result) = cache.get(&request_str) {
+        info!("Embedding Request already cached");
+        return serde_json::from_str(result).unwrap();
+    }
+    match local.embed(full_content) {
+        Ok(emb) => {
+            cache.set(request_str, serde_json::to_string(&emb).unwrap());
+            emb
+        }
+        Err(e) => {
+            eprintln!("Local embedding failed: {}", e);
+            vec![]
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct OllamaRequest {
     model: String,
@@ -276,6 +375,8 @@ struct OllamaRequest {
 struct OllamaOptions {
     num_predict: i32,
     stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -312,6 +413,8 @@ struct OpenAIChatRequest {
     max_tokens: i32,
     temperature: f32,
     stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
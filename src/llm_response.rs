@@ -1,26 +1,43 @@
 use regex::Regex;
+use serde::Serialize;
 
+use std::path::Path;
+
+use crate::generate_into;
 use crate::{utils::remove_comments, Lang};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Project {
-    pub dependencies: String,
-    pub additional_config: Vec<String>,
-    pub solution_code: String,
-    pub test_code: String,
+    // Paths relative to the sandbox with their content, the manifest first
+    pub files: Vec<ProjectFile>,
+    // Folder of an existing project the files are laid over, see generate_into
+    pub base: Option<String>,
     pub install_dependency_command: Option<String>,
     pub build_command: String,
     pub test_command: String,
     pub lang: Lang,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectFile {
+    pub path: String,
+    pub content: String,
+}
+
+impl ProjectFile {
+    pub fn new(path: &str, content: String) -> ProjectFile {
+        ProjectFile {
+            path: path.to_string(),
+            content,
+        }
+    }
+}
+
 impl Default for Project {
     fn default() -> Self {
         Self {
-            dependencies: String::new(),
-            additional_config: vec![],
-            solution_code: String::new(),
-            test_code: String::new(),
+            files: vec![],
+            base: None,
             install_dependency_command: None,
             build_command: String::new(),
             test_command: String::new(),
@@ -29,6 +46,35 @@ impl Default for Project {
     }
 }
 
+impl Project {
+    // Content of the file at `path`, empty when the project has no such file
+    pub fn file(&self, path: &str) -> &str {
+        self.files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.content.as_str())
+            .unwrap_or("")
+    }
+
+    // All the files in one string, what build results are cached by
+    pub fn sources(&self) -> String {
+        let files = self
+            .files
+            .iter()
+            .map(|f| format!("{}\n{}", f.path, f.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        match &self.base {
+            Some(base) => format!(
+                "{}\n{}",
+                generate_into::fingerprint(Path::new(base), &self.lang),
+                files
+            ),
+            None => files,
+        }
+    }
+}
+
 pub struct LLMResponse;
 
 impl LLMResponse {
@@ -126,8 +172,10 @@ impl LLMResponse {
                     }
                 }
                 Project {
-                    dependencies: cargo_toml,
-                    solution_code: lib_rs,
+                    files: vec![
+                        ProjectFile::new("Cargo.toml", cargo_toml),
+                        ProjectFile::new("src/lib.rs", lib_rs),
+                    ],
                     build_command: remove_comments(&build),
                     test_command: remove_comments(&test),
                     lang: Lang::Rust,
@@ -219,9 +267,11 @@ impl LLMResponse {
                 }
 
                 Project {
-                    dependencies: pkg_json,
-                    solution_code: solution,
-                    test_code: test_js,
+                    files: vec![
+                        ProjectFile::new("package.json", pkg_json),
+                        ProjectFile::new("src/solution.js", solution),
+                        ProjectFile::new("src/solution.test.js", test_js),
+                    ],
                     test_command: test,
                     install_dependency_command: Some(install_dependency_command),
                     lang: Lang::JavaScript,
@@ -316,11 +366,20 @@ impl LLMResponse {
                 }
 
                 Project {
-                    dependencies: pom_xml,
-                    solution_code: solution_java,
-                    test_code: test_java,
+                    files: vec![
+                        ProjectFile::new("pom.xml", pom_xml),
+                        ProjectFile::new(
+                            "src/main/java/com/example/solution/Solution.java",
+                            solution_java,
+                        ),
+                        ProjectFile::new(
+                            "src/test/java/com/example/solution/SolutionTest.java",
+                            test_java,
+                        ),
+                    ],
                     build_command,
                     test_command,
+                    lang: Lang::Java,
                     ..Default::default()
                 }
             }
@@ -409,9 +468,11 @@ impl LLMResponse {
                     }
                 }
                 Project {
-                    dependencies: gradle,
-                    solution_code: solution_kotlin,
-                    test_code: test_kotlin,
+                    files: vec![
+                        ProjectFile::new("build.gradle", gradle),
+                        ProjectFile::new("src/main/kotlin/Solution.kt", solution_kotlin),
+                        ProjectFile::new("src/test/kotlin/SolutionTest.kt", test_kotlin),
+                    ],
                     build_command,
                     test_command,
                     lang: Lang::Kotlin,
@@ -505,9 +566,11 @@ impl LLMResponse {
                 }
                 Project {
                     install_dependency_command: Some(install_dependency_command),
-                    dependencies: composer,
-                    solution_code: solution_php,
-                    test_code: test_php,
+                    files: vec![
+                        ProjectFile::new("composer.json", composer),
+                        ProjectFile::new("src/Solution.php", solution_php),
+                        ProjectFile::new("tests/SolutionTest.php", test_php),
+                    ],
                     test_command,
                     lang: Lang::Php,
                     ..Default::default()
@@ -595,9 +658,11 @@ impl LLMResponse {
                     }
                 }
                 Project {
-                    dependencies: requirements,
-                    solution_code: solution_py,
-                    test_code: test_py,
+                    files: vec![
+                        ProjectFile::new("requirements.txt", requirements),
+                        ProjectFile::new("solution.py", solution_py),
+                        ProjectFile::new("test.py", test_py),
+                    ],
                     install_dependency_command: Some(install_dependency_command),
                     test_command: test,
                     lang: Lang::Python,
@@ -682,9 +747,11 @@ impl LLMResponse {
                     }
                 }
                 Project {
-                    dependencies: sbt,
-                    solution_code: solution_sc,
-                    test_code: test_sc,
+                    files: vec![
+                        ProjectFile::new("build.sbt", sbt),
+                        ProjectFile::new("src/main/scala/Solution.scala", solution_sc),
+                        ProjectFile::new("src/test/scala/SolutionTest.scala", test_sc),
+                    ],
                     build_command: build,
                     test_command: test,
                     lang: Lang::Scala,
@@ -772,9 +839,11 @@ impl LLMResponse {
                     }
                 }
                 Project {
-                    dependencies: package,
-                    solution_code: solution_sw,
-                    test_code: test_sw,
+                    files: vec![
+                        ProjectFile::new("Package.swift", package),
+                        ProjectFile::new("Sources/Solution/Solution.swift", solution_sw),
+                        ProjectFile::new("Tests/SolutionTests/SolutionTests.swift", test_sw),
+                    ],
                     build_command: build,
                     test_command: test,
                     lang: Lang::Swift,
@@ -868,10 +937,12 @@ impl LLMResponse {
                     }
                 }
                 Project {
-                    dependencies: package,
-                    solution_code: solution,
-                    test_code,
-                    additional_config: vec![typescript_config],
+                    files: vec![
+                        ProjectFile::new("package.json", package),
+                        ProjectFile::new("tsconfig.json", typescript_config),
+                        ProjectFile::new("src/solution.ts", solution),
+                        ProjectFile::new("src/solution.test.ts", test_code),
+                    ],
                     build_command: build,
                     test_command: test,
                     lang: Lang::TypeScript,
@@ -883,6 +954,46 @@ impl LLMResponse {
             }
         }
     }
+
+    // Any number of files, for "generate application": every "**path**" section with
+    // a code block is a file, and the Install, Build and Test sections hold the commands
+    pub fn parse_files(response: &str, language: Lang) -> Project {
+        let re_code_block = Regex::new(r"(?s)```.*?\n(.*?)```").unwrap();
+        let positions = LLMResponse::parse_positions(response);
+        let mut project = Project {
+            lang: language,
+            ..Default::default()
+        };
+        for i in 0..positions.len() - 1 {
+            let (section_name, start) = &positions[i];
+            let section_content = &response[*start..positions[i + 1].1];
+            let Some(cap) = re_code_block.captures(section_content) else {
+                continue;
+            };
+            let content = cap.get(1).unwrap().as_str().to_string();
+            let name = section_name.trim().trim_matches('`');
+            match name {
+                "Install" => project.install_dependency_command = Some(remove_comments(&content)),
+                "Build" => project.build_command = remove_comments(&content),
+                "Test" => project.test_command = remove_comments(&content),
+                _ if is_project_path(name) => {
+                    // A later version of the same file wins
+                    project.files.retain(|f| f.path != name);
+                    project.files.push(ProjectFile::new(name, content));
+                }
+                _ => {}
+            }
+        }
+        project
+    }
+}
+
+// A relative path inside the sandbox, such as "src/config.rs", "Cargo.toml" or "Makefile"
+fn is_project_path(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['/', '\\'])
+        && std::path::Path::new(name).is_relative()
+        && name.split(['/', '\\']).all(|part| part != "..")
 }
 
 mod tests {
@@ -895,9 +1006,13 @@ mod tests {
                 crate::llm_response::LLMResponse::parse_llm_response(&response, crate::Lang::Java);
 
             println!("{:#?}", project);
-            assert!(!project.dependencies.is_empty());
-            assert!(!project.solution_code.is_empty());
-            assert!(!project.test_code.is_empty());
+            assert!(!project.file("pom.xml").is_empty());
+            assert!(!project
+                .file("src/main/java/com/example/solution/Solution.java")
+                .is_empty());
+            assert!(!project
+                .file("src/test/java/com/example/solution/SolutionTest.java")
+                .is_empty());
             assert!(!project.build_command.is_empty());
             assert!(!project.test_command.is_empty());
         }
@@ -914,10 +1029,25 @@ mod tests {
             project.test_command = crate::utils::remove_comments(&project.test_command);
 
             println!("{:#?}", project);
-            assert!(!project.dependencies.is_empty());
-            assert!(!project.solution_code.is_empty());
+            assert!(!project.file("Cargo.toml").is_empty());
+            assert!(!project.file("src/lib.rs").is_empty());
             assert!(!project.build_command.is_empty());
             assert!(!project.test_command.is_empty());
         }
     }
+
+    #[test]
+    fn test_parse_files() {
+        let response = "Here is the project.\n\n**Cargo.toml**\n```toml\n[package]\nname = \"app\"\n```\n\n**src/main.rs**\n```rust\nmod config;\nfn main() {}\n```\n\n**`src/config.rs`**\n```rust\npub struct Config;\n```\n\n**../outside.rs**\n```rust\n```\n\n**/etc/passwd**\n```\n```\n\n**Makefile**\n```make\nall:\n```\n\n**Build**\n```bash\ncargo build\n```\n\n**Test**\n```bash\ncargo test\n```\n";
+        let project =
+            crate::llm_response::LLMResponse::parse_files(response, crate::Lang::Rust);
+        let paths: Vec<&str> = project.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["Cargo.toml", "src/main.rs", "src/config.rs", "Makefile"]
+        );
+        assert_eq!(project.file("src/config.rs"), "pub struct Config;\n");
+        assert_eq!(project.build_command, "cargo build");
+        assert_eq!(project.test_command, "cargo test");
+    }
 }
@@ -0,0 +1,81 @@
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use std::path::Path;
+use tokenizers::{Tokenizer, TruncationParams};
+
+// A BERT embedding model such as bge-small-en-v1.5 run on the CPU, so "index" and
+// "ask" work offline without an embedding server. The folder holds the files of
+// the Hugging Face repository: config.json, tokenizer.json and model.safetensors.
+pub struct LocalEmbedder {
+    // Reported as the embedding model of the index, e.g. "local:bge-small-en-v1.5"
+    name: String,
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl LocalEmbedder {
+    pub fn load(dir: &str) -> Result<LocalEmbedder, String> {
+        let dir_path = Path::new(dir);
+        let read = |file: &str| {
+            std::fs::read_to_string(dir_path.join(file))
+                .map_err(|e| format!("Cant read {} of the model {}: {}", file, dir, e))
+        };
+        let config: Config = serde_json::from_str(&read("config.json")?)
+            .map_err(|e| format!("Invalid config.json of the model {}: {}", dir, e))?;
+        let mut tokenizer = Tokenizer::from_file(dir_path.join("tokenizer.json"))
+            .map_err(|e| format!("Cant load tokenizer.json of the model {}: {}", dir, e))?;
+        // Longer files are cut, like the embedding APIs do
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(|e| e.to_string())?;
+        let device = Device::Cpu;
+        // Safety: the weights file is memory mapped and must not change while it is used
+        let vb = unsafe {
+            VarBuilder::from_mmaped_safetensors(
+                &[dir_path.join("model.safetensors")],
+                DTYPE,
+                &device,
+            )
+        }
+        .map_err(|e| format!("Cant load model.safetensors of the model {}: {}", dir, e))?;
+        let model = BertModel::load(vb, &config).map_err(|e| e.to_string())?;
+        let folder = std::fs::canonicalize(dir_path).unwrap_or_else(|_| dir_path.to_path_buf());
+        let name = folder
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| dir.to_string());
+        Ok(LocalEmbedder {
+            name: format!("local:{}", name),
+            model,
+            tokenizer,
+            device,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The normalized [CLS] vector, the pooling of the bge models
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>, String> {
+        let encoding = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(|e| e.to_string())?;
+        let run = || -> candle_core::Result<Vec<f32>> {
+            let ids = Tensor::new(encoding.get_ids(), &self.device)?.unsqueeze(0)?;
+            let type_ids = ids.zeros_like()?;
+            let mask = Tensor::new(encoding.get_attention_mask(), &self.device)?.unsqueeze(0)?;
+            let output = self.model.forward(&ids, &type_ids, Some(&mask))?;
+            let cls = output.get(0)?.get(0)?;
+            let norm = cls.sqr()?.sum_all()?.sqrt()?;
+            cls.broadcast_div(&norm)?.to_vec1::<f32>()
+        };
+        run().map_err(|e| e.to_string())
+    }
+}
@@ -7,13 +7,43 @@ use std::sync::Mutex;
 
 use crate::docker_tool::*;
 
+// Progress and diagnostic messages. They go to stderr when stdout carries the
+// result of a non-interactive "ask", so scripts can read stdout as is.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if *$crate::LOG_TO_STDERR.lock().unwrap() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod ask_command;
+mod batch_command;
 mod build_tool;
 mod cache;
+mod candidates;
+mod citations;
+mod documents;
+mod events;
 mod file_explorer;
+mod generate_into;
+mod index_command;
+mod lexical_index;
 mod llm_api;
+#[cfg(feature = "local-embeddings")]
+mod local_embedding;
 mod llm_prompt;
 mod llm_response;
+mod project_index;
+mod query_rewrite;
+mod rerank;
+mod review_command;
+mod session;
 mod state_machine;
+mod symbol_index;
+mod toolchain;
 mod utils;
 mod vector_utils;
 // This section has added by AB to immpement an issue #19
@@ -21,6 +51,7 @@ mod docker_tool;
 // End of section of issue #19
 
 static VERBOSE: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+static LOG_TO_STDERR: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
 
 // This section has added by AB to immpement an issue #19
 
@@ -77,10 +108,11 @@ Usage:
             Arg::new("lang")
                 .long("lang")
                 .value_name("LANG")
-                .help("Sets the programming language")
+                .help("Sets the programming language (\"auto\" detects every language in the folder, index and ask only)")
                 .default_value("rust")
                 .global(true)
                 .value_parser(*&[
+                    "auto",
                     "rust",
                     "java",
                     "javascript",
@@ -109,16 +141,211 @@ Usage:
                 .default_value("bge-large")
                 .global(true),
         )
+        .arg(
+            Arg::new("emb-model")
+                .long("emb-model")
+                .value_name("PATH")
+                .help("Compute embeddings in process with a local BERT model folder (config.json, tokenizer.json, model.safetensors), e.g. bge-small-en-v1.5; needs the local-embeddings feature")
+                .global(true),
+        )
         .subcommand(
             Command::new("generate")
                 .about("Generate code")
                 .alias("g")
                 .arg(
                     Arg::new("type")
-                        .help("Type of generation")
+                        .help("Type of generation: a function with tests, or an application with modules, config and tests")
                         .value_parser(*&["function", "application"])
                         .default_value("function")
                         .index(1),
+                )
+                .arg(
+                    Arg::new("into")
+                        .long("into")
+                        .value_name("FILE")
+                        .help("Add the function and its tests to FILE of an existing project, e.g. src/utils.rs; the project is built and tested in a copy and only changed when the tests pass"),
+                )
+                .arg(
+                    Arg::new("events")
+                        .long("events")
+                        .value_name("FILE")
+                        .help("Write the state machine events (state changes, build and test results) to FILE as JSON lines"),
+                )
+                .arg(
+                    Arg::new("report")
+                        .long("report")
+                        .value_name("FILE")
+                        .help("Write the report of the run (outcome, attempts, time per state) to FILE as JSON"),
+                )
+                .arg(
+                    Arg::new("candidates")
+                        .long("candidates")
+                        .value_name("N")
+                        .help("Sample N first generations, build and test them side by side and go on with the first passing or best one")
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("prompt")
+                        .long("prompt")
+                        .value_name("TEXT")
+                        .help("Generate from this description instead of asking for it (a description piped to stdin works too)")
+                        .conflicts_with("prompt-file"),
+                )
+                .arg(
+                    Arg::new("prompt-file")
+                        .long("prompt-file")
+                        .value_name("FILE")
+                        .help("Generate from the description in this file"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .help("Create the docker image without asking when it is missing")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("output-dir")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .help("Write the generated files to DIR once they build and pass the tests")
+                        .conflicts_with("into"),
+                ),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Generate a function for every task of a JSON lines file")
+                .arg(
+                    Arg::new("tasks")
+                        .help("JSON lines file, one task per line: {\"id\": ..., \"description\": ..., \"lang\": ..., \"tests\": ...}, only the description is required")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .long("jobs")
+                        .value_name("N")
+                        .help("Tasks run at the same time, each in its own sandbox")
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write one JSON line per task (status, code, attempts, tokens, time) to FILE")
+                        .default_value("results.jsonl"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .help("Create the docker images without asking when they are missing")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("session")
+                .about("List, show and resume the saved runs of generate")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the sessions, newest first"))
+                .subcommand(
+                    Command::new("show")
+                        .about("Show the attempts of a session with their files and checks")
+                        .arg(Arg::new("id").required(true).index(1)),
+                )
+                .subcommand(
+                    Command::new("resume")
+                        .about("Go on with the last attempt of a session")
+                        .arg(Arg::new("id").required(true).index(1))
+                        .arg(
+                            Arg::new("attempts")
+                                .long("attempts")
+                                .value_name("N")
+                                .help("Rewrites allowed in this run, as many as generate allows by default")
+                                .value_parser(clap::value_parser!(u64).range(1..)),
+                        )
+                        .arg(
+                            Arg::new("hint")
+                                .long("hint")
+                                .value_name("TEXT")
+                                .help("Added to the explanation of the session, e.g. what the last attempt got wrong"),
+                        )
+                        .arg(
+                            Arg::new("yes")
+                                .long("yes")
+                                .help("Create the docker image without asking when it is missing")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("index")
+                .about("Build or update the index of a folder used by ask")
+                .alias("i")
+                .arg(
+                    Arg::new("path")
+                        .help("Path to the source code folder")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("status")
+                        .long("status")
+                        .help("Show the indexed files, stale entries and the embedding model instead of indexing")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("watch")
+                        .long("watch")
+                        .help("Keep running and update the index when files change")
+                        .conflicts_with("status")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("include")
+                        .long("include")
+                        .value_name("GLOB")
+                        .help("Only index files matching this glob, relative to the path (repeatable)")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .long("exclude")
+                        .value_name("GLOB")
+                        .help("Do not index files matching this glob, relative to the path (repeatable)")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("max-file-size")
+                        .long("max-file-size")
+                        .value_name("BYTES")
+                        .help("Skip files larger than this size")
+                        .default_value("1048576")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("follow-links")
+                        .long("follow-links")
+                        .help("Follow symbolic links (loops are detected and skipped)")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("summarize")
+                        .long("summarize")
+                        .help("Ask the LLM to explain every file and index the explanation with the code (slower first run, summaries are cached)")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("no-embeddings")
+                        .long("no-embeddings")
+                        .help("Build a keyword-only index, no embedding model needed (ask --retrieval lexical)")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("docs")
+                        .long("docs")
+                        .help("Also index Markdown, YAML and SQL files (README, ADRs, OpenAPI specs, migrations) split in chunks")
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
@@ -127,9 +354,131 @@ Usage:
                 .alias("a")
                 .arg(
                     Arg::new("path")
-                        .help("Path to the source code folder")
+                        .help("Source code folders, each indexed with 'rustsn index'; \"label=path\" names a folder, by default it is labelled by its name")
                         .required(true)
+                        .num_args(1..)
                         .index(1),
+                )
+                .arg(
+                    Arg::new("scope")
+                        .long("scope")
+                        .value_name("SCOPE")
+                        .help("Only search one root or a folder of it, e.g. \"backend\" or \"backend/src/api\" (\"/scope X\" changes it in the session)"),
+                )
+                .arg(
+                    Arg::new("rewrite")
+                        .long("rewrite")
+                        .value_name("MODE")
+                        .help("Also search with an LLM rewrite of the question: keywords (likely identifiers) or hyde (a hypothetical code snippet), merged with the results of the question")
+                        .default_value("none")
+                        .value_parser(*&["none", "keywords", "hyde"]),
+                )
+                .arg(
+                    Arg::new("no-docs")
+                        .long("no-docs")
+                        .help("Only retrieve code, leave out the documents indexed with 'index --docs'")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("retrieval")
+                        .long("retrieval")
+                        .value_name("MODE")
+                        .help("How to find relevant files: hybrid (embeddings + keywords), semantic (embeddings only) or lexical (keywords only, no embedding model needed)")
+                        .default_value("hybrid")
+                        .value_parser(*&["hybrid", "semantic", "lexical"]),
+                )
+                .arg(
+                    Arg::new("top-k")
+                        .long("top-k")
+                        .value_name("K")
+                        .help("Number of closest files given to the LLM (\"/k N\" changes it in the session)")
+                        .default_value("3")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("context-tokens")
+                        .long("context-tokens")
+                        .value_name("TOKENS")
+                        .help("Add definitions used by the retrieved files and code calling them, up to this many tokens (Rust, JavaScript, TypeScript, C#; 0 disables)")
+                        .default_value("1500")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("rerank")
+                        .long("rerank")
+                        .value_name("N")
+                        .help("Score the N best retrieved files for relevance and keep the top-k best (0 disables reranking)")
+                        .default_value("0")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("reranker")
+                        .long("reranker")
+                        .value_name("llm|URL")
+                        .help("Who scores the files for --rerank: the LLM or a cross-encoder /rerank endpoint, e.g. http://127.0.0.1:8080/rerank")
+                        .default_value("llm"),
+                )
+                .arg(
+                    Arg::new("question")
+                        .long("question")
+                        .value_name("TEXT")
+                        .help("Answer this question and exit instead of starting a session (a question piped to stdin works too)")
+                        .conflicts_with("question-file"),
+                )
+                .arg(
+                    Arg::new("question-file")
+                        .long("question-file")
+                        .value_name("FILE")
+                        .help("Answer the question from this file and exit"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Output of a non-interactive run: text or json (answer, files with scores, model, timings)")
+                        .default_value("text")
+                        .value_parser(*&["text", "json"]),
+                ),
+        )
+        .subcommand(
+            Command::new("review")
+                .about("Review the changes of a git diff with related code from the index")
+                .alias("r")
+                .arg(
+                    Arg::new("range")
+                        .help("Commits to review as given to 'git diff', e.g. main..HEAD (default: the uncommitted changes)")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .value_name("PATH")
+                        .help("Folder of the git repository, indexed with 'rustsn index'")
+                        .default_value("."),
+                )
+                .arg(
+                    Arg::new("top-k")
+                        .long("top-k")
+                        .value_name("K")
+                        .help("Number of related files from the index shown with each changed hunk")
+                        .default_value("2")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("context-tokens")
+                        .long("context-tokens")
+                        .value_name("TOKENS")
+                        .help("Token budget of the related files of each hunk")
+                        .default_value("3000")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Output: text (path:line: comment) or json")
+                        .default_value("text")
+                        .value_parser(*&["text", "json"]),
                 ),
         )
         .get_matches();
@@ -137,6 +486,48 @@ Usage:
     let verbose = matches.get_one::<bool>("verbose").unwrap();
     *VERBOSE.lock().unwrap() = *verbose;
 
+    // Read the question of a non-interactive "ask" first: then all the progress
+    // messages go to stderr and stdout only gets the answer
+    let mut ask_question = None;
+    let mut ask_format = ask_command::OutputFormat::Text;
+    if let Some(ask_matches) = matches.subcommand_matches("ask") {
+        ask_format = ask_matches
+            .get_one::<String>("format")
+            .unwrap()
+            .parse()
+            .unwrap();
+        ask_question = ask_command::read_question_arg(
+            ask_matches.get_one::<String>("question"),
+            ask_matches.get_one::<String>("question-file"),
+        )
+        .unwrap_or_else(|e| {
+            ask_command::report_error(ask_format, &e);
+            std::process::exit(e.code);
+        });
+        if ask_question.is_none() && ask_format == ask_command::OutputFormat::Json {
+            let e = ask_command::AskError::new(
+                ask_command::EXIT_USAGE,
+                "--format json needs --question, --question-file or a question on stdin"
+                    .to_string(),
+            );
+            ask_command::report_error(ask_format, &e);
+            std::process::exit(e.code);
+        }
+        *LOG_TO_STDERR.lock().unwrap() = ask_question.is_some();
+    }
+    // The review comments are the only output on stdout, e.g. for a CI job
+    let review_format = match matches.subcommand_matches("review") {
+        Some(review_matches) => {
+            *LOG_TO_STDERR.lock().unwrap() = true;
+            review_matches
+                .get_one::<String>("format")
+                .unwrap()
+                .parse()
+                .unwrap()
+        }
+        None => ask_command::OutputFormat::Text,
+    };
+
     // This section has added by AB to immpement an issue #19
 
     let environment = matches.get_one::<String>("environment");
@@ -147,92 +538,73 @@ Usage:
         Some(s) => {
             match s.as_str () {
                 "host" => { 
-                    println!("Selected environment: host");
+                    info!("Selected environment: host");
                     *ENVTYPE.lock().unwrap() = docker_tool::EnvironmentType::host; 
                 },
                 "docker" => {
-                    println!("Selected environment: docker");
+                    info!("Selected environment: docker");
                     *ENVTYPE.lock().unwrap() = docker_tool::EnvironmentType::docker; 
                 }
                 _ => {
-                    println!("Unknown type of the environment");
-                    std::process::exit(1);
+                    info!("Unknown type of the environment");
+                    std::process::exit(ask_command::EXIT_USAGE);
                 }
             }
         }
     }
     // End of section of issue #19
 
-    let lang: Lang = matches
-        .get_one::<String>("lang")
-        .unwrap()
-        .parse()
-        .unwrap_or_else(|err| {
+    let lang_arg = matches.get_one::<String>("lang").unwrap();
+    let auto_lang = lang_arg == "auto";
+    if auto_lang
+        && !matches!(
+            matches.subcommand_name(),
+            Some("ask") | Some("index") | Some("review")
+        )
+    {
+        eprintln!("--lang=auto is only supported by the index, ask and review commands");
+        std::process::exit(ask_command::EXIT_USAGE);
+    }
+    let lang: Lang = if auto_lang {
+        Lang::Unknown
+    } else {
+        lang_arg.parse().unwrap_or_else(|err| {
             eprintln!("{}", err);
-            std::process::exit(1);
-        });
+            std::process::exit(ask_command::EXIT_USAGE);
+        })
+    };
 
     // Optionally, handle the selected language
     match lang {
-        Lang::Rust => println!("Selected language: Rust"),
-        Lang::Java => println!("Selected language: Java"),
-        Lang::Scala => println!("Selected language: Scala"),
-        Lang::JavaScript => println!("Selected language: JavaScript"),
-        Lang::Php => println!("Selected language: PHP"),
-        Lang::Python => println!("Selected language: Python"),
-        Lang::Kotlin => println!("Selected language: Kotlin"),
-        Lang::Swift => println!("Selected language: Swift"),
-        Lang::TypeScript => println!("Selected language: TypeScript"),
-        Lang::CSharp => println!("Selected language: C#"),
+        Lang::Unknown if auto_lang => info!("Selected language: auto"),
+        Lang::Rust => info!("Selected language: Rust"),
+        Lang::Java => info!("Selected language: Java"),
+        Lang::Scala => info!("Selected language: Scala"),
+        Lang::JavaScript => info!("Selected language: JavaScript"),
+        Lang::Php => info!("Selected language: PHP"),
+        Lang::Python => info!("Selected language: Python"),
+        Lang::Kotlin => info!("Selected language: Kotlin"),
+        Lang::Swift => info!("Selected language: Swift"),
+        Lang::TypeScript => info!("Selected language: TypeScript"),
+        Lang::CSharp => info!("Selected language: C#"),
         _ => {
-            println!("Unimplemented language: {:?}", lang);
-            std::process::exit(1);
+            info!("Unimplemented language: {:?}", lang);
+            std::process::exit(ask_command::EXIT_USAGE);
         }
     }
 
     let mut cache = cache::Cache::new();
 
-    let prompt_file_path = format!("prompt/{}.txt", lang);
-    if !std::path::Path::new(&prompt_file_path).exists() {
-        println!(
-            "Warning: Cant find \"{}\". Downloading it from https://github.com/evgenyigumnov/rustsn/raw/HEAD/{}",
-            prompt_file_path, prompt_file_path
-        );
-
-        let url = format!(
-            "https://github.com/evgenyigumnov/rustsn/raw/HEAD/{}",
-            prompt_file_path
-        );
-        match reqwest::blocking::get(&url) {
-            Ok(response) => {
-                if response.status().is_success() {
-                    let content = response.text().unwrap();
-                    // Create directories if they don't exist
-                    if let Some(parent) = std::path::Path::new(&prompt_file_path).parent() {
-                        std::fs::create_dir_all(parent).unwrap();
-                    }
-                    // Write the content to the prompt file
-                    std::fs::write(&prompt_file_path, content).unwrap();
-                } else {
-                    eprintln!(
-                        "Failed to download the prompt file: HTTP {}",
-                        response.status()
-                    );
-                    std::process::exit(1);
-                }
-            }
-            Err(err) => {
-                eprintln!("Error downloading the prompt file: {}", err);
-                std::process::exit(1);
-            }
-        }
-    }
-
-    let prompt = llm_prompt::Prompt::new(format!("prompt/{}.txt", lang).as_str());
+    // The language prompts are only used by "generate", "ask" builds its prompts in code
+    let prompt = if auto_lang {
+        llm_prompt::Prompt::default()
+    } else {
+        load_prompt(&lang.to_string())
+    };
     // if file token.txt exists
     let llm = if std::path::Path::new("token.txt").exists() {
-        println!("Use OpenAI API");
-        println!("");
+        info!("Use OpenAI API");
+        info!("");
         let token = std::fs::read_to_string("token.txt").unwrap();
         llm_api::LLMApi::new(llm_api::ModelType::OpenAI {
             api_key: token.trim().to_string(),
@@ -246,10 +618,10 @@ Usage:
                 eprintln!("{}", err);
                 std::process::exit(1);
             });
-        println!("Warning: Cant find \"token.txt\" file for OpenAI API integration.");
-        println!("Use Ollama API: {}", OLLAMA_API);
-        println!("Use Ollama model: {}", ollama_model);
-        println!("");
+        info!("Warning: Cant find \"token.txt\" file for OpenAI API integration.");
+        info!("Use Ollama API: {}", OLLAMA_API);
+        info!("Use Ollama model: {}", ollama_model);
+        info!("");
 
         let emb: String = matches
             .get_one::<String>("ollemb")
@@ -264,201 +636,421 @@ Usage:
             emb,
         })
     };
+    // A local embedding model replaces the embedding API of Ollama or OpenAI
+    let llm = match matches.get_one::<String>("emb-model") {
+        #[cfg(feature = "local-embeddings")]
+        Some(dir) => {
+            let embedder = local_embedding::LocalEmbedder::load(dir).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            info!("Use local embedding model: {}", embedder.name());
+            llm.with_local_embeddings(embedder)
+        }
+        #[cfg(not(feature = "local-embeddings"))]
+        Some(_) => {
+            eprintln!("--emb-model needs rustsn built with the local-embeddings feature: cargo install rustsn --features local-embeddings");
+            std::process::exit(ask_command::EXIT_USAGE);
+        }
+        None => llm,
+    };
 
-    println!(
+    info!(
         "Use '\\' char in the end of line for multiline mode or just copy-paste multiline text."
     );
-    println!("");
+    info!("");
 
-    println!("For launch work with AI, type ENTER twice after the last line of the prompt.");
-    println!("");
+    info!("For launch work with AI, type ENTER twice after the last line of the prompt.");
+    info!("");
 
     let command = matches.subcommand_name();
     match command {
         Some("generate") => {
-            println!("Explain what the function should do:");
-            let question: String = ask();
-
-            // This section has added by AB to immpement an issue #19
-
-            // Check the Environment type
-            // If "docker" then run docker_tool::check_docker and if it returns false 
-            // (which means language specific image not found)
-            // then docker_tool::run create_image and then create container
-            // And then run the container by docker_tool::run_container
-            match *ENVTYPE.lock().unwrap() {
-                docker_tool::EnvironmentType::docker => {
-                    // Check the Docker
-                    match docker_tool::check_docker (&lang) {
-                        Ok(true) => {
-                            if let Err(e) = docker_tool::create_container(&lang) {
-                                panic!("Failed to create container: {}", e);
-                            }
-                        }
-                        Ok(false) => {
-                            // Create the image and the container
-                            // Firstly ask the user about his agreement to create an image
-                            println!("Do you agree to create an image? (y/n)");
-                            let mut answer = String::new();
-                            std::io::stdin().read_line(&mut answer).unwrap();
-                            if answer.trim() == "y" {
-                                // Create the image
-                                if let Err(e) = docker_tool::create_image(&lang) {
-                                    panic!("Failed to create image: {}", e);
-                                }
-                                if let Err(e) = docker_tool::create_container(&lang) {
-                                    panic!("Failed to create container: {}", e);
-                                }
-                            } else {
-                                panic!("Couldn't create an image cause of user restictions, you can create an image manually");
-                            }
-
+            let generate_matches = matches.subcommand_matches("generate").unwrap();
+            let application = generate_matches.get_one::<String>("type").unwrap() == "application";
+            let target = match generate_matches.get_one::<String>("into") {
+                Some(_) if application => {
+                    eprintln!("--into is only supported by generate function");
+                    std::process::exit(ask_command::EXIT_USAGE);
+                }
+                Some(file) => {
+                    let toolchain = toolchain::toolchain(&lang).unwrap_or_else(|| {
+                        eprintln!("{}", toolchain::unsupported(&lang));
+                        std::process::exit(ask_command::EXIT_USAGE);
+                    });
+                    let into = generate_into::IntoTarget::new(file, toolchain.as_ref())
+                        .unwrap_or_else(|e| {
+                            eprintln!("{}", e);
+                            std::process::exit(ask_command::EXIT_USAGE);
+                        });
+                    info!("Generate into {}", into.path().display());
+                    state_machine::Target::Into(into)
+                }
+                None if application => state_machine::Target::Application,
+                None => state_machine::Target::Function,
+            };
+            let question = ask_command::read_question_arg(
+                generate_matches.get_one::<String>("prompt"),
+                generate_matches.get_one::<String>("prompt-file"),
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e.message);
+                std::process::exit(e.code);
+            });
+            let question: String = match question {
+                Some(question) => question,
+                None => {
+                    info!("Explain what the {} should do:", target);
+                    ask()
+                }
+            };
+            start_docker(&lang, generate_matches.get_flag("yes"));
 
-                        },
-                        Err(e) => panic!("Couldn't connect to Docker: {}", e)
-                    };
-                    // Run the container
-                    match docker_tool::run_container(&lang) {
-                        Ok(true) => {} /* do nothing */
-                        Ok(false) => {
-                            panic!("Failed to run the container");
+            let prompt = match target {
+                state_machine::Target::Function => prompt,
+                _ => target_prompt(&target, &lang),
+            };
+            let into = match &target {
+                state_machine::Target::Into(into) => Some(into.path().to_string_lossy().to_string()),
+                _ => None,
+            };
+            let session = session::Session::create(&lang, &target.to_string(), into, &question)
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(state_machine::EXIT_INFRA);
+                });
+            let mut options = state_machine::RunOptions::new(target);
+            options.events = generate_matches.get_one::<String>("events").cloned();
+            options.output_dir = generate_matches.get_one::<String>("output-dir").cloned();
+            options.candidates = *generate_matches.get_one::<u64>("candidates").unwrap() as usize;
+            options.session = Some(session);
+            run_generate(
+                &lang,
+                &question,
+                &prompt,
+                &mut cache,
+                &llm,
+                &options,
+                generate_matches.get_one::<String>("report"),
+            );
+        }
+        Some("session") => {
+            let session_matches = matches.subcommand_matches("session").unwrap();
+            let open = |id: &String| {
+                session::Session::open(id).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(ask_command::EXIT_USAGE);
+                })
+            };
+            match session_matches.subcommand() {
+                Some(("list", _)) => {
+                    for s in session::Session::list() {
+                        println!(
+                            "{}  {:<9}  {:<10}  {:<11}  {:>2} rewrites  {}",
+                            s.id,
+                            s.status,
+                            s.lang,
+                            s.target,
+                            s.attempts,
+                            s.question.lines().next().unwrap_or("")
+                        );
+                    }
+                }
+                Some(("show", show_matches)) => {
+                    let session = open(show_matches.get_one::<String>("id").unwrap());
+                    session.print();
+                }
+                Some(("resume", resume_matches)) => {
+                    let mut session = open(resume_matches.get_one::<String>("id").unwrap());
+                    let lang: Lang = session.manifest.lang.parse().unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        std::process::exit(ask_command::EXIT_USAGE);
+                    });
+                    let toolchain = toolchain::toolchain(&lang).unwrap_or_else(|| {
+                        eprintln!("{}", toolchain::unsupported(&lang));
+                        std::process::exit(ask_command::EXIT_USAGE);
+                    });
+                    let target = match (&session.manifest.into, session.manifest.target.as_str()) {
+                        (Some(file), _) => {
+                            let into = generate_into::IntoTarget::new(file, toolchain.as_ref())
+                                .unwrap_or_else(|e| {
+                                    eprintln!("{}", e);
+                                    std::process::exit(ask_command::EXIT_USAGE);
+                                });
+                            state_machine::Target::Into(into)
                         }
-                        Err(e) => panic!("Failed to run the container: {}", e)
+                        (None, "application") => state_machine::Target::Application,
+                        _ => state_machine::Target::Function,
                     };
+                    // The hint stays in the question of the later rewrites and resumes
+                    if let Some(hint) = resume_matches.get_one::<String>("hint") {
+                        session.manifest.question =
+                            format!("{}\n\n{}", session.manifest.question, hint.trim());
+                    }
+                    let question = session.manifest.question.clone();
+                    // A session that failed before its first answer starts over
+                    let resume = session.last_project(lang.clone()).ok().map(|mut project| {
+                        if let state_machine::Target::Into(into) = &target {
+                            into.set_base(&mut project);
+                        }
+                        project
+                    });
+                    info!("Resume session {} from attempt {}", session.manifest.id, session.first_attempt);
+                    start_docker(&lang, resume_matches.get_flag("yes"));
+                    let prompt = target_prompt(&target, &lang);
+                    let mut options = state_machine::RunOptions::new(target);
+                    if let Some(attempts) = resume_matches.get_one::<u64>("attempts") {
+                        options.max_attempts = *attempts as u32;
+                    }
+                    options.session = Some(session);
+                    options.resume = resume;
+                    run_generate(&lang, &question, &prompt, &mut cache, &llm, &options, None);
                 }
-                docker_tool::EnvironmentType::host => {
-                    // Do nothing
+                _ => unreachable!(),
+            }
+        }
+        Some("batch") => {
+            let batch_matches = matches.subcommand_matches("batch").unwrap();
+            let path: &String = batch_matches.get_one("tasks").unwrap();
+            let tasks = batch_command::read_tasks(path, &lang).unwrap_or_else(|e| {
+                eprintln!("{}", e.message);
+                std::process::exit(e.code);
+            });
+            // One prompt and one container for every language of the tasks
+            let mut langs: Vec<Lang> = Vec::new();
+            for task in &tasks {
+                if !langs.contains(&task.lang) {
+                    langs.push(task.lang.clone());
                 }
+            }
+            let prompts: HashMap<String, llm_prompt::Prompt> = langs
+                .iter()
+                .map(|lang| (lang.to_string(), load_prompt(&lang.to_string())))
+                .collect();
+            for lang in &langs {
+                start_docker(lang, batch_matches.get_flag("yes"));
+            }
+            let options = batch_command::BatchOptions {
+                jobs: *batch_matches.get_one::<u64>("jobs").unwrap() as usize,
+                output: batch_matches.get_one::<String>("output").unwrap().clone(),
             };
-            // End of section of issue #19
-
-            state_machine::run_state_machine(&lang, &question, &prompt, &mut cache, &llm);
-            println!("++++++++ Finished ++++++++++++");
+            info!("Run {} tasks, {} at a time", tasks.len(), options.jobs);
+            let results =
+                batch_command::run_batch(&tasks, &prompts, &options, &llm, &mut cache);
+            for lang in &langs {
+                stop_docker(lang);
+            }
+            let results = results.unwrap_or_else(|e| {
+                eprintln!("{}", e.message);
+                std::process::exit(e.code);
+            });
+            info!("Results written to {}", options.output);
+            info!("++++++++ Finished ++++++++++++");
+            std::process::exit(batch_command::exit_code(&results));
         }
-        Some("ask") => {
-            let path: &String = matches
-                .subcommand_matches("ask")
-                .unwrap()
-                .get_one("path")
-                .unwrap();
-            println!("Path: {:?}", path);
-            match lang {
-                Lang::Rust => {
-                    handle_ask_command(
-                        path,
-                        &lang,
-                        &llm,
-                        &mut cache,
-                        &prompt,
-                        vec![String::from("rs"), String::from("toml")],
-                        vec![String::from("target")],
-                        "Explain how this code works and what it do:",
-                        "Use functions from code above to give answer for this question:",
-                    );
-                }
-                Lang::CSharp => {
-                    handle_ask_command(
-                        path,
-                        &lang,
-                        &llm,
-                        &mut cache,
-                        &prompt,
-                        vec![String::from("cs")],
-                        vec![String::from("bin"), String::from("obj")],
-                        "Explain how this code works and what it does:",
-                        "Use the code above to answer the following question:",
-                    );
+        Some("index") => {
+            let index_matches = matches.subcommand_matches("index").unwrap();
+            let path: &String = index_matches.get_one("path").unwrap();
+            info!("Path: {:?}", path);
+            let explore_options = file_explorer::ExploreOptions {
+                include_globs: index_matches
+                    .get_many::<String>("include")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                exclude_globs: index_matches
+                    .get_many::<String>("exclude")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                max_file_size: *index_matches.get_one::<u64>("max-file-size").unwrap(),
+                follow_links: index_matches.get_flag("follow-links"),
+                ..Default::default()
+            };
+            for globs in [&explore_options.include_globs, &explore_options.exclude_globs] {
+                if let Err(e) = file_explorer::build_glob_set(globs) {
+                    eprintln!("{}", e);
+                    std::process::exit(ask_command::EXIT_USAGE);
                 }
-                Lang::JavaScript => {
-                    handle_ask_command(
-                        path,
-                        &lang,
-                        &llm,
-                        &mut cache,
-                        &prompt,
-                        vec![String::from("js")],
-                        vec![String::from("node_modules")],
-                        "Explain how this JavaScript code works and what it does:",
-                        "Use the code above to answer the following question:",
-                    );
+            }
+            let status = index_matches.get_flag("status");
+            let langs = if status {
+                vec![]
+            } else if auto_lang {
+                let detected = index_command::detect_languages(path, &explore_options);
+                if detected.is_empty() {
+                    eprintln!("No supported source files found in {}", path);
+                    std::process::exit(ask_command::EXIT_NO_FILES);
                 }
-                _ => {
-                    println!("Unsupported language: {:?}", lang);
+                info!(
+                    "Detected languages: {}",
+                    detected
+                        .iter()
+                        .map(|l| l.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                detected
+            } else if lang.source_extensions().is_empty() {
+                info!("Unsupported language: {:?}", lang);
+                std::process::exit(1);
+            } else {
+                vec![lang.clone()]
+            };
+            let options = index_command::IndexOptions {
+                langs,
+                explore: explore_options,
+                summarize: index_matches.get_flag("summarize"),
+                embeddings: !index_matches.get_flag("no-embeddings"),
+                docs: index_matches.get_flag("docs"),
+            };
+            if let Err(e) = index_command::handle_index_command(
+                path,
+                &options,
+                status,
+                index_matches.get_flag("watch"),
+                &llm,
+                &mut cache,
+                &prompt,
+            ) {
+                eprintln!("Error: {}", e.message);
+                std::process::exit(e.code);
+            }
+        }
+        Some("ask") => {
+            let ask_matches = matches.subcommand_matches("ask").unwrap();
+            let paths: Vec<String> = ask_matches
+                .get_many::<String>("path")
+                .unwrap()
+                .cloned()
+                .collect();
+            info!("Path: {:?}", paths);
+            let retrieval: project_index::Retrieval = ask_matches
+                .get_one::<String>("retrieval")
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|err| {
+                    eprintln!("{}", err);
                     std::process::exit(1);
-                }
+                });
+            let options = ask_command::AskOptions {
+                retrieval,
+                top_k: *ask_matches.get_one::<usize>("top-k").unwrap(),
+                context_tokens: *ask_matches.get_one::<usize>("context-tokens").unwrap(),
+                rerank: match *ask_matches.get_one::<usize>("rerank").unwrap() {
+                    0 => None,
+                    candidates => Some(rerank::Rerank {
+                        candidates,
+                        reranker: match ask_matches.get_one::<String>("reranker").unwrap().as_str() {
+                            "llm" => rerank::Reranker::Llm,
+                            url => rerank::Reranker::CrossEncoder {
+                                url: url.to_string(),
+                            },
+                        },
+                    }),
+                },
+                format: ask_format,
+                scope: ask_matches.get_one::<String>("scope").cloned(),
+                docs: !ask_matches.get_flag("no-docs"),
+                rewrite: match ask_matches.get_one::<String>("rewrite").unwrap().as_str() {
+                    "none" => None,
+                    mode => mode.parse().ok(),
+                },
+            };
+            if let Err(e) = ask_command::handle_ask_command(
+                &paths,
+                &llm,
+                &mut cache,
+                &prompt,
+                options,
+                ask_question,
+            ) {
+                ask_command::report_error(ask_format, &e);
+                std::process::exit(e.code);
             }
 
-            println!("++++++++ Finished ++++++++++++");
+            info!("++++++++ Finished ++++++++++++");
+        }
+        Some("review") => {
+            let review_matches = matches.subcommand_matches("review").unwrap();
+            let path: &String = review_matches.get_one("path").unwrap();
+            let options = review_command::ReviewOptions {
+                range: review_matches.get_one::<String>("range").cloned(),
+                top_k: *review_matches.get_one::<usize>("top-k").unwrap(),
+                context_tokens: *review_matches.get_one::<usize>("context-tokens").unwrap(),
+                format: review_format,
+            };
+            if let Err(e) =
+                review_command::handle_review_command(path, &options, &llm, &mut cache, &prompt)
+            {
+                ask_command::report_error(review_format, &e);
+                std::process::exit(e.code);
+            }
         }
         _ => {
-            println!("Unknown command, please use 'generate' or 'ask'");
+            info!("Unknown command, please use 'generate', 'batch', 'session', 'index', 'ask' or 'review'");
             std::process::exit(1);
         }
     }
 }
 
-fn handle_ask_command(
-    path: &String,
-    _lang: &Lang,
-    llm: &llm_api::LLMApi,
-    cache: &mut cache::Cache,
-    prompt: &llm_prompt::Prompt,
-    extensions: Vec<String>,
-    exclude_dirs: Vec<String>,
-    _explain_prompt: &str,
-    answer_prompt: &str,
-) {
-    let files = file_explorer::explore_files(&path, &extensions, &exclude_dirs);
-    let mut vectors: HashMap<String, Vec<f32>> = HashMap::new();
-    for file in &files {
-        println!("File: {:?}", file);
-        let content_file = std::fs::read_to_string(file).unwrap();
-        let content = format!("# {}\r\n{}", file, content_file);
-
-        // let prompt_template = format!("{}\r\n{}", content, _explain_prompt);
-        // let llm_code_explanation = llm.request(&prompt_template, &Vec::new(), cache, prompt);
-        // let full_content = format!("{}\r\n{}", content, llm_code_explanation);
-        // let emb = llm.emb(&content, cache, &full_content);
-        let emb = llm.emb(&content, cache, &content);
-        vectors.insert(file.clone(), emb);
-    }
-
-    println!("Enter the question about your project sources:");
-    let question: String = ask();
-    let target_emb = llm.emb(&question, cache, &question);
-    let result = vector_utils::find_closest(&target_emb, &vectors);
-    let limited_result = result.iter().take(3).collect::<Vec<_>>();
-    println!("Find closest files:");
-    for (k, _v) in &limited_result {
-        println!("File: {}", k);
-    }
-    let files_content_vec = limited_result
-        .iter()
-        .map(|(k, _)| {
-            let content = std::fs::read_to_string(k).unwrap();
-            format!("# {} \r\n{}", k, content)
-        })
-        .collect::<Vec<_>>();
-    let files_content = files_content_vec.join("\r\n");
+// prompt/<name>.txt, downloaded from the repository when it is missing
+fn load_prompt(name: &str) -> llm_prompt::Prompt {
+    let prompt_file_path = format!("prompt/{}.txt", name);
+    if !std::path::Path::new(&prompt_file_path).exists() {
+        info!(
+            "Warning: Cant find \"{}\". Downloading it from https://github.com/evgenyigumnov/rustsn/raw/HEAD/{}",
+            prompt_file_path, prompt_file_path
+        );
 
-    let prompt_template = format!("{}\r\n{}\r\n{}", files_content, answer_prompt, question);
-    if *VERBOSE.lock().unwrap() {
-        println!("Request: {}", prompt_template);
+        let url = format!(
+            "https://github.com/evgenyigumnov/rustsn/raw/HEAD/{}",
+            prompt_file_path
+        );
+        match reqwest::blocking::get(&url) {
+            Ok(response) => {
+                if response.status().is_success() {
+                    let content = response.text().unwrap();
+                    // Create directories if they don't exist
+                    if let Some(parent) = std::path::Path::new(&prompt_file_path).parent() {
+                        std::fs::create_dir_all(parent).unwrap();
+                    }
+                    // Write the content to the prompt file
+                    std::fs::write(&prompt_file_path, content).unwrap();
+                } else {
+                    eprintln!(
+                        "Failed to download the prompt file: HTTP {}",
+                        response.status()
+                    );
+                    std::process::exit(state_machine::EXIT_INFRA);
+                }
+            }
+            Err(err) => {
+                eprintln!("Error downloading the prompt file: {}", err);
+                std::process::exit(state_machine::EXIT_INFRA);
+            }
+        }
     }
-    let answer = llm.request(&prompt_template, &Vec::new(), cache, prompt);
-
-    println!("++++++++ Answer ++++++++++++");
 
-    println!("Answer: {}", answer);
+    llm_prompt::Prompt::new(prompt_file_path.as_str())
 }
 
 fn ask() -> String {
+    read_question().unwrap_or_else(|| "\r\n".to_string())
+}
+
+// Same as ask() but returns None once stdin is closed and nothing was typed
+fn read_question() -> Option<String> {
     let mut question;
     let mut lines = vec![];
     let mut start_sec = 0 as u128;
+    let mut eof = false;
     loop {
         let mut line = String::new();
-        std::io::stdin().read_line(&mut line).unwrap();
+        if std::io::stdin().read_line(&mut line).unwrap() == 0 {
+            eof = true;
+            break;
+        }
 
         if line.ends_with("\\\r\n") {
             let mut line_clone = line.clone();
@@ -492,12 +1084,15 @@ fn ask() -> String {
     }
     question = lines.join("");
     question = question.trim().to_string();
+    if eof && question.is_empty() {
+        return None;
+    }
     question.push('\r');
     question.push('\n');
-    question
+    Some(question)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 enum Lang {
     Rust,
     Java,
@@ -536,6 +1131,52 @@ impl Display for Lang {
     }
 }
 
+impl Lang {
+    // Source files indexed by the "ask" command, also used to detect the language
+    pub fn source_extensions(&self) -> &'static [&'static str] {
+        match self {
+            Lang::Rust => &["rs"],
+            Lang::Java => &["java"],
+            Lang::JavaScript => &["js", "jsx", "mjs", "cjs"],
+            Lang::TypeScript => &["ts", "tsx"],
+            Lang::Scala => &["scala"],
+            Lang::Python => &["py"],
+            Lang::Kotlin => &["kt"],
+            Lang::Php => &["php"],
+            Lang::Swift => &["swift"],
+            Lang::CSharp => &["cs"],
+            _ => &[],
+        }
+    }
+
+    // Build and dependency files indexed together with the sources
+    pub fn config_extensions(&self) -> &'static [&'static str] {
+        match self {
+            Lang::Rust => &["toml"],
+            Lang::Scala => &["sbt"],
+            Lang::Kotlin => &["kts"],
+            _ => &[],
+        }
+    }
+
+    // Build output and dependency folders skipped by the "ask" command
+    pub fn excluded_dirs(&self) -> &'static [&'static str] {
+        match self {
+            Lang::Rust => &["target"],
+            Lang::Java => &["target", "build"],
+            Lang::JavaScript => &["node_modules", "dist"],
+            Lang::TypeScript => &["node_modules", "dist"],
+            Lang::Scala => &["target"],
+            Lang::Python => &["__pycache__", "venv", "env", "build", "dist"],
+            Lang::Kotlin => &["build"],
+            Lang::Php => &["vendor"],
+            Lang::Swift => &["Pods"],
+            Lang::CSharp => &["bin", "obj"],
+            _ => &[],
+        }
+    }
+}
+
 impl FromStr for Lang {
     type Err = String;
 
@@ -558,8 +1199,106 @@ impl FromStr for Lang {
     }
 }
 
+// One prompt file for the applications and one for --into, for every language
+fn target_prompt(target: &state_machine::Target, lang: &Lang) -> llm_prompt::Prompt {
+    match target {
+        state_machine::Target::Application => load_prompt("application"),
+        state_machine::Target::Into(_) => load_prompt("into"),
+        state_machine::Target::Function => load_prompt(&lang.to_string()),
+    }
+}
+
+// Runs "generate" or "session resume" and exits with the code of the outcome
+fn run_generate(
+    lang: &Lang,
+    question: &str,
+    prompt: &llm_prompt::Prompt,
+    cache: &mut cache::Cache,
+    llm: &llm_api::LLMApi,
+    options: &state_machine::RunOptions,
+    report_path: Option<&String>,
+) -> ! {
+    let report = state_machine::run_state_machine(lang, question, prompt, cache, llm, options);
+    stop_docker(lang);
+    let (report, _) = report.unwrap_or_else(|e| {
+        eprintln!("{}", e.message);
+        std::process::exit(e.code);
+    });
+    report.print();
+    if let Some(path) = report_path {
+        if let Err(e) = report.save(path) {
+            eprintln!("{}", e);
+            std::process::exit(state_machine::EXIT_INFRA);
+        }
+    }
+    if let Some(session) = &options.session {
+        info!("Session {} saved in {}", session.manifest.id, session.dir.display());
+    }
+    info!("++++++++ Finished ++++++++++++");
+    std::process::exit(state_machine::exit_code(&report));
+}
+
 // This section has added by AB to immpement an issue #19
 
+// Check the Environment type
+// If "docker" then run docker_tool::check_docker and if it returns false
+// (which means language specific image not found)
+// then docker_tool::run create_image and then create container
+// And then run the container by docker_tool::run_container
+// `yes` creates a missing image without asking
+fn start_docker(lang: &Lang, yes: bool) {
+    if !matches!(*ENVTYPE.lock().unwrap(), docker_tool::EnvironmentType::docker) {
+        return;
+    }
+    let infra_error = |message: String| -> ! {
+        eprintln!("{}", message);
+        std::process::exit(state_machine::EXIT_INFRA);
+    };
+    // Check the Docker
+    match docker_tool::check_docker(lang) {
+        Ok(true) => {
+            if let Err(e) = docker_tool::create_container(lang) {
+                infra_error(format!("Failed to create container: {}", e));
+            }
+        }
+        Ok(false) => {
+            // Create the image and the container
+            // Firstly ask the user about his agreement to create an image
+            let agreed = yes || {
+                info!("Do you agree to create an image? (y/n)");
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer).unwrap();
+                answer.trim() == "y"
+            };
+            if !agreed {
+                eprintln!("Couldn't create an image cause of user restictions, you can create an image manually or pass --yes");
+                std::process::exit(ask_command::EXIT_USAGE);
+            }
+            if let Err(e) = docker_tool::create_image(lang) {
+                infra_error(format!("Failed to create image: {}", e));
+            }
+            if let Err(e) = docker_tool::create_container(lang) {
+                infra_error(format!("Failed to create container: {}", e));
+            }
+        }
+        Err(e) => infra_error(format!("Couldn't connect to Docker: {}", e)),
+    };
+    // Run the container
+    match docker_tool::run_container(lang) {
+        Ok(true) => {}
+        Ok(false) => infra_error("Failed to run the container".to_string()),
+        Err(e) => infra_error(format!("Failed to run the container: {}", e)),
+    };
+}
+
+// The container is kept between the attempts and removed once done
+fn stop_docker(lang: &Lang) {
+    if matches!(*ENVTYPE.lock().unwrap(), docker_tool::EnvironmentType::docker) {
+        let _ = docker_tool::stop_container(lang);
+        let _ = docker_tool::remove_container(lang);
+    }
+}
+
 impl Lang {
     pub fn get_image_name(&self) -> Result<&'static str, String>  {
         match self {
@@ -0,0 +1,402 @@
+use bincode::{config, Decode, Encode};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::documents;
+use crate::lexical_index::LexicalIndex;
+use crate::symbol_index::{RelatedCode, SymbolIndex};
+use crate::vector_utils::{find_closest, reciprocal_rank_fusion, IvfIndex, ANN_MIN_VECTORS};
+
+const INDEX_DIR: &str = ".rustsn/index";
+// How many candidates each side contributes before the rankings are fused
+const HYBRID_CANDIDATES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retrieval {
+    Hybrid,
+    Semantic,
+    Lexical,
+}
+
+impl Retrieval {
+    pub fn uses_embeddings(&self) -> bool {
+        *self != Retrieval::Lexical
+    }
+}
+
+impl std::str::FromStr for Retrieval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hybrid" => Ok(Retrieval::Hybrid),
+            "semantic" => Ok(Retrieval::Semantic),
+            "lexical" => Ok(Retrieval::Lexical),
+            _ => Err(format!("Unsupported retrieval mode: {}", s)),
+        }
+    }
+}
+
+// How the index was built, so "index --status" and updates find the same files
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone)]
+pub struct IndexSettings {
+    pub langs: Vec<String>,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub max_file_size: u64,
+    pub follow_links: bool,
+    pub summarize: bool,
+    pub embeddings: bool,
+    // Markdown, YAML and SQL files are indexed too, see documents.rs
+    pub docs: bool,
+}
+
+// Persistent per-project index built by "rustsn index" and used by "ask".
+// One file per indexed folder, stored next to cache.bin in the working directory.
+#[derive(Encode, Decode, PartialEq, Debug, Default)]
+pub struct ProjectIndex {
+    root: String,
+    settings: IndexSettings,
+    // Vectors of different models or sizes cannot be compared, one model per index
+    embedding_model: String,
+    dimensions: u32,
+    // Content hash of every indexed file, to find stale entries
+    hashes: HashMap<String, u64>,
+    vectors: HashMap<String, Vec<f32>>,
+    ann: Option<IvfIndex>,
+    lexical: LexicalIndex,
+    // LLM summaries of --summarize by file, with the hash of the content they describe
+    summaries: HashMap<String, (u64, String)>,
+    symbols: SymbolIndex,
+    // Chunk ids of every document file, the file itself has no vector or text entry
+    chunks: HashMap<String, Vec<String>>,
+}
+
+impl ProjectIndex {
+    pub fn load(root: &str) -> ProjectIndex {
+        let path = index_path(root);
+        if !std::path::Path::new(&path).exists() {
+            return ProjectIndex {
+                root: root.to_string(),
+                ..Default::default()
+            };
+        }
+        let encoded = fs::read(&path).unwrap();
+        match bincode::decode_from_slice::<ProjectIndex, _>(&encoded, config::standard()) {
+            Ok((index, _)) => index,
+            Err(e) => {
+                info!(
+                    "Warning: Cant read index {}: {}. It has to be built again.",
+                    path, e
+                );
+                ProjectIndex {
+                    root: root.to_string(),
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = index_path(&self.root);
+        if let Some(parent) = std::path::Path::new(&path).parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        let encoded: Vec<u8> = bincode::encode_to_vec(self, config::standard()).unwrap();
+        // Write then rename, so an "ask" running next to "index --watch" never
+        // reads a half written index
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, encoded).unwrap();
+        fs::rename(&tmp_path, path).unwrap();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    pub fn path(&self) -> String {
+        index_path(&self.root)
+    }
+
+    pub fn settings(&self) -> &IndexSettings {
+        &self.settings
+    }
+
+    // Files are indexed again when what goes into their documents changes
+    pub fn set_settings(&mut self, settings: IndexSettings) {
+        if settings.summarize != self.settings.summarize {
+            self.hashes.clear();
+            if !settings.summarize {
+                self.summaries.clear();
+            }
+        }
+        self.settings = settings;
+    }
+
+    // Empty when the index has no embeddings
+    pub fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions as usize
+    }
+
+    // Switching the embedding model drops every vector of the previous one
+    pub fn set_embedding_model(&mut self, model: &str) {
+        if self.embedding_model == model {
+            return;
+        }
+        if !self.vectors.is_empty() && !model.is_empty() {
+            info!(
+                "Embedding model changed from {} to {}, all files are embedded again",
+                self.embedding_model, model
+            );
+        }
+        self.vectors.clear();
+        self.ann = None;
+        self.dimensions = 0;
+        self.embedding_model = model.to_string();
+    }
+
+    pub fn hash(&self, id: &str) -> Option<u64> {
+        self.hashes.get(id).copied()
+    }
+
+    pub fn set_hash(&mut self, id: &str, content_hash: u64) {
+        self.hashes.insert(id.to_string(), content_hash);
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &String> {
+        self.hashes.keys()
+    }
+
+    // Files and document chunks that can be found by a search
+    #[cfg(test)]
+    pub fn id_count(&self) -> usize {
+        self.lexical.ids().count()
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.values().map(|ids| ids.len()).sum()
+    }
+
+    // Files with embeddings, a document counts when all its chunks have one
+    pub fn embedded_count(&self) -> usize {
+        self.files().filter(|f| self.has_vectors(f)).count()
+    }
+
+    fn has_vectors(&self, id: &str) -> bool {
+        match self.chunks.get(id) {
+            Some(ids) => ids.iter().all(|c| self.vectors.contains_key(c)),
+            None => self.vectors.contains_key(id),
+        }
+    }
+
+    pub fn summary_count(&self) -> usize {
+        self.summaries.len()
+    }
+
+    // Nothing to do for this file: same content and everything the settings ask for is there.
+    // Documents are never summarized.
+    pub fn is_fresh(&self, id: &str, content_hash: u64) -> bool {
+        self.hash(id) == Some(content_hash)
+            && (!self.settings.embeddings || self.has_vectors(id))
+            && (!self.settings.summarize
+                || documents::is_document(id)
+                || self.summary(id, content_hash).is_some())
+    }
+
+    pub fn insert(&mut self, id: &str, emb: Vec<f32>) -> Result<(), String> {
+        if self.dimensions == 0 {
+            self.dimensions = emb.len() as u32;
+        } else if emb.len() != self.dimensions as usize {
+            return Err(format!(
+                "Embedding of {} has {} dimensions, the index has {}",
+                id,
+                emb.len(),
+                self.dimensions
+            ));
+        }
+        let previous = self.vectors.get(id);
+        if previous == Some(&emb) {
+            return Ok(());
+        }
+        if let Some(ann) = self.ann.as_mut() {
+            if previous.is_some() {
+                ann.remove(id);
+            }
+            ann.insert(id, &emb);
+        }
+        self.vectors.insert(id.to_string(), emb);
+        Ok(())
+    }
+
+    pub fn insert_text(&mut self, id: &str, text: &str) {
+        self.lexical.insert(id, text);
+    }
+
+    pub fn insert_symbols(&mut self, id: &str, content: &str) {
+        self.symbols.insert(id, content);
+    }
+
+    // Replaces the chunks of a document, the ones that are gone are removed
+    pub fn set_chunks(&mut self, file: &str, ids: Vec<String>) {
+        let old = self.chunks.remove(file).unwrap_or_default();
+        for id in old.iter().filter(|id| !ids.contains(id)) {
+            self.vectors.remove(id);
+            self.lexical.remove(id);
+            if let Some(ann) = self.ann.as_mut() {
+                ann.remove(id);
+            }
+        }
+        self.chunks.insert(file.to_string(), ids);
+    }
+
+    // Definitions used by `id` and code using its definitions, see SymbolIndex::related
+    pub fn related(&self, id: &str, exclude: &[String]) -> Vec<RelatedCode> {
+        self.symbols.related(id, exclude)
+    }
+
+    // The summary is reused only while the file content is unchanged
+    pub fn summary(&self, id: &str, content_hash: u64) -> Option<&str> {
+        match self.summaries.get(id) {
+            Some((hash, summary)) if *hash == content_hash => Some(summary),
+            _ => None,
+        }
+    }
+
+    pub fn set_summary(&mut self, id: &str, content_hash: u64, summary: String) {
+        self.summaries
+            .insert(id.to_string(), (content_hash, summary));
+    }
+
+    // Drop every entry for which `keep` returns false, e.g. files deleted from disk.
+    // `keep` is called with file paths, chunks go with their document.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        let removed: Vec<String> = self
+            .vectors
+            .keys()
+            .chain(self.lexical.ids())
+            .chain(self.summaries.keys())
+            .chain(self.symbols.files())
+            .chain(self.hashes.keys())
+            .chain(self.chunks.keys())
+            .filter(|id| !keep(documents::id_file(id)))
+            .cloned()
+            .collect();
+        for id in removed {
+            self.vectors.remove(&id);
+            self.lexical.remove(&id);
+            self.summaries.remove(&id);
+            self.symbols.remove(&id);
+            self.hashes.remove(&id);
+            self.chunks.remove(&id);
+            if let Some(ann) = self.ann.as_mut() {
+                ann.remove(&id);
+            }
+        }
+    }
+
+    // Builds the ANN index once the project is large enough and retrains it when
+    // the number of vectors has drifted too far from the training set.
+    pub fn refresh_ann(&mut self) {
+        if self.vectors.len() < ANN_MIN_VECTORS {
+            self.ann = None;
+            return;
+        }
+        let retrain = match &self.ann {
+            None => true,
+            Some(ann) => ann.needs_retrain(self.vectors.len()),
+        };
+        if retrain {
+            info!("Build ANN index for {} vectors", self.vectors.len());
+            self.ann = IvfIndex::build(&self.vectors);
+        }
+    }
+
+    // `question_emb` is None when no embedding is available, the lexical side
+    // then answers alone whatever mode was requested. Only the ids `keep` accepts
+    // are ranked, so a filter never empties the candidate lists.
+    pub fn search(
+        &self,
+        question: &str,
+        question_emb: Option<&[f32]>,
+        mode: Retrieval,
+        k: usize,
+        keep: &dyn Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        match (mode, question_emb) {
+            (Retrieval::Semantic, Some(emb)) => self.find_closest(emb, k, keep),
+            (Retrieval::Hybrid, Some(emb)) => reciprocal_rank_fusion(
+                &[
+                    self.find_closest(emb, HYBRID_CANDIDATES, keep),
+                    self.lexical.search(question, HYBRID_CANDIDATES, keep),
+                ],
+                k,
+            ),
+            _ => self.lexical.search(question, k, keep),
+        }
+    }
+
+    pub fn find_closest(
+        &self,
+        target: &[f32],
+        k: usize,
+        keep: &dyn Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        find_closest(target, &self.vectors, k, self.ann.as_ref(), keep)
+    }
+}
+
+fn index_path(root: &str) -> String {
+    let canonical = fs::canonicalize(root)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| root.to_string());
+    let name: String = canonical
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}/{}.bin", INDEX_DIR, name.trim_matches('_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_filters_before_truncation() {
+        // 2100 files outside the scope match better than the one inside, enough for the ANN
+        let mut index = ProjectIndex::default();
+        for i in 0..2100 {
+            let id = format!("other/file_{}.rs", i);
+            index.insert(&id, vec![1.0, i as f32 * 0.001]).unwrap();
+            index.insert_text(&id, "fn parse_config() { parse config }");
+        }
+        index.insert("scope/config.rs", vec![0.0, 5.0]).unwrap();
+        index.insert_text("scope/config.rs", "fn load() { config }");
+        let keep = |id: &str| id.starts_with("scope/");
+        let emb = [1.0, 0.0];
+        for ann in [false, true] {
+            if ann {
+                index.refresh_ann();
+                assert!(index.ann.is_some());
+            }
+            for mode in [Retrieval::Hybrid, Retrieval::Semantic, Retrieval::Lexical] {
+                let found = index.search("parse config", Some(&emb), mode, 3, &keep);
+                assert_eq!(found.len(), 1, "{:?}", mode);
+                assert_eq!(found[0].0, "scope/config.rs");
+            }
+        }
+        let found = index.search("parse config", Some(&emb), Retrieval::Hybrid, 3, &|_| true);
+        assert!(found.iter().all(|(id, _)| id.starts_with("other/")));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::cache::Cache;
+use crate::llm_api::LLMApi;
+use crate::llm_prompt::Prompt;
+
+const KEYWORDS_PROMPT: &str = "List the function, type, variable and file names that code answering the question below likely uses, for example parse_config ConfigLoader settings.toml. Reply with the names separated by spaces and nothing else.";
+const HYDE_PROMPT: &str = "Write a short code snippet that could be the part of the project answering the question below. Reply with the code only, without explanation.";
+
+// How the question is rewritten before retrieval. Short questions in plain words
+// embed far from code, a list of identifiers or a made-up snippet lands closer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rewrite {
+    // Likely identifiers, which also helps the keyword search
+    Keywords,
+    // Hypothetical Document Embeddings: a snippet that could answer the question
+    Hyde,
+}
+
+impl std::str::FromStr for Rewrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keywords" => Ok(Rewrite::Keywords),
+            "hyde" => Ok(Rewrite::Hyde),
+            _ => Err(format!("Unsupported query rewrite: {}", s)),
+        }
+    }
+}
+
+// The query to search with next to the question itself
+pub fn rewrite_query(
+    question: &str,
+    rewrite: Rewrite,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<String, String> {
+    let instruction = match rewrite {
+        Rewrite::Keywords => KEYWORDS_PROMPT,
+        Rewrite::Hyde => HYDE_PROMPT,
+    };
+    let prompt_template = format!("{}\r\nQuestion: {}", instruction, question);
+    // Cached by the LLM cache, asking the same question again costs nothing
+    let response = llm.try_request(&prompt_template, &Vec::new(), cache, prompt)?;
+    let rewritten = strip_code_fences(&response);
+    if rewritten.is_empty() {
+        return Err("empty LLM response".to_string());
+    }
+    Ok(rewritten)
+}
+
+// The snippet without its ```rust fences, they would only add noise to the query
+fn strip_code_fences(response: &str) -> String {
+    response
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_code_fences() {
+        let response = "```rust\nfn save_cache(cache: &Cache) {\n    fs::write(\"cache.bin\", bytes);\n}\n```\n";
+        assert_eq!(
+            strip_code_fences(response),
+            "fn save_cache(cache: &Cache) {\n    fs::write(\"cache.bin\", bytes);\n}"
+        );
+        assert_eq!(
+            strip_code_fences(" Cache save_cache \n"),
+            "Cache save_cache"
+        );
+        assert_eq!("HyDE".parse::<Rewrite>(), Ok(Rewrite::Hyde));
+        assert!("none".parse::<Rewrite>().is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::documents;
+use crate::llm_api::LLMApi;
+use crate::llm_prompt::Prompt;
+
+// "3: 8", "[3] - 7.5" or "File 3: 9/10"
+static RE_SCORE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?m)^\D*?(\d+)\]?(?:[ \t]*[:=\-][ \t]*|[ \t]+)(\d+(?:\.\d+)?)").unwrap()
+});
+
+// Only the beginning of each candidate is shown to the reranker
+const RERANK_SNIPPET_CHARS: usize = 1500;
+const RERANK_PROMPT: &str = "Rate how relevant each file above is to answering the question below, from 0 (unrelated) to 10 (answers it). Reply with one line per file in the form \"number: score\" and nothing else.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reranker {
+    // The answer LLM scores all candidates in one request
+    Llm,
+    // A cross-encoder behind a text-embeddings-inference compatible /rerank endpoint
+    CrossEncoder { url: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rerank {
+    // How many retrieved files are scored before the best top-k are kept
+    pub candidates: usize,
+    pub reranker: Reranker,
+}
+
+#[derive(Serialize)]
+struct CrossEncoderRequest<'a> {
+    query: &'a str,
+    texts: Vec<&'a str>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CrossEncoderScore {
+    index: usize,
+    score: f32,
+}
+
+// Reorders `candidates` by relevance to the question and keeps the best `k`.
+// When the reranker fails the retrieval order is kept.
+pub fn rerank(
+    question: &str,
+    candidates: Vec<(String, f32)>,
+    k: usize,
+    reranker: &Reranker,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Vec<(String, f32)> {
+    let snippets: Vec<String> = candidates
+        .iter()
+        .map(|(id, _)| {
+            let content = documents::read_source(id)
+                .map(|(_, _, c)| c)
+                .unwrap_or_default();
+            content.chars().take(RERANK_SNIPPET_CHARS).collect()
+        })
+        .collect();
+    let scores = match reranker {
+        Reranker::Llm => llm_scores(question, &candidates, &snippets, llm, cache, prompt),
+        Reranker::CrossEncoder { url } => cross_encoder_scores(question, &snippets, url, cache),
+    };
+    match scores {
+        Ok(scores) => order_by_scores(candidates, &scores, k),
+        Err(e) => {
+            info!("Warning: rerank failed, keep the retrieval order: {}", e);
+            candidates.into_iter().take(k).collect()
+        }
+    }
+}
+
+fn llm_scores(
+    question: &str,
+    candidates: &[(String, f32)],
+    snippets: &[String],
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<HashMap<usize, f32>, String> {
+    let files: Vec<String> = candidates
+        .iter()
+        .zip(snippets)
+        .enumerate()
+        .map(|(i, ((file, _), snippet))| format!("File {}: {}\r\n{}", i + 1, file, snippet))
+        .collect();
+    let prompt_template = format!(
+        "{}\r\n{}\r\nQuestion: {}",
+        files.join("\r\n\r\n"),
+        RERANK_PROMPT,
+        question
+    );
+    // Cached by the LLM cache like every other request
+    let response = llm.try_request(&prompt_template, &Vec::new(), cache, prompt)?;
+    let scores = parse_llm_scores(&response, candidates.len());
+    if scores.is_empty() {
+        return Err(format!("no scores in the LLM response: {}", response));
+    }
+    Ok(scores)
+}
+
+// Candidate index (0 based) to score, numbers outside the candidate list are ignored
+fn parse_llm_scores(response: &str, count: usize) -> HashMap<usize, f32> {
+    let mut scores = HashMap::new();
+    for cap in RE_SCORE.captures_iter(response) {
+        let (Ok(number), Ok(score)) = (cap[1].parse::<usize>(), cap[2].parse::<f32>()) else {
+            continue;
+        };
+        if number >= 1 && number <= count {
+            scores.entry(number - 1).or_insert(score);
+        }
+    }
+    scores
+}
+
+fn cross_encoder_scores(
+    question: &str,
+    snippets: &[String],
+    url: &str,
+    cache: &mut Cache,
+) -> Result<HashMap<usize, f32>, String> {
+    let request = CrossEncoderRequest {
+        query: question,
+        texts: snippets.iter().map(|s| s.as_str()).collect(),
+    };
+    let request_str = format!("{} {}", url, serde_json::to_string(&request).unwrap());
+    let response: Vec<CrossEncoderScore> = match cache.get(&request_str) {
+        Some(cached) => {
+            info!("Rerank request already cached");
+            serde_json::from_str(cached).map_err(|e| e.to_string())?
+        }
+        None => {
+            info!("Request to reranker in progress");
+            let client = Client::builder()
+                .timeout(Duration::from_secs(60 * 5))
+                .build()
+                .unwrap();
+            let response = client
+                .post(url)
+                .json(&request)
+                .send()
+                .map_err(|e| format!("Network error: {}", e))?
+                .json::<Vec<CrossEncoderScore>>()
+                .map_err(|e| format!("Failed to parse JSON response: {}", e))?;
+            cache.set(request_str, serde_json::to_string(&response).unwrap());
+            response
+        }
+    };
+    Ok(response
+        .into_iter()
+        .filter(|s| s.index < snippets.len())
+        .map(|s| (s.index, s.score))
+        .collect())
+}
+
+// Highest score first, unscored candidates last; ties keep the retrieval order
+fn order_by_scores(
+    candidates: Vec<(String, f32)>,
+    scores: &HashMap<usize, f32>,
+    k: usize,
+) -> Vec<(String, f32)> {
+    let mut scored: Vec<(usize, String, f32)> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(i, (file, _))| (i, file, scores.get(&i).copied().unwrap_or(f32::MIN)))
+        .collect();
+    scored.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    scored
+        .into_iter()
+        .take(k)
+        .map(|(_, file, score)| (file, score))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_llm_scores() {
+        let response = "1: 3\n[2] - 9.5\nFile 3: 7/10\n7: 10\n10\nThe rest is unrelated.";
+        let scores = parse_llm_scores(response, 4);
+        assert_eq!(scores.len(), 3);
+        assert_eq!(scores[&0], 3.0);
+        assert_eq!(scores[&1], 9.5);
+        assert_eq!(scores[&2], 7.0);
+    }
+
+    #[test]
+    fn test_order_by_scores() {
+        let candidates: Vec<(String, f32)> = ["a.rs", "b.rs", "c.rs", "d.rs"]
+            .iter()
+            .map(|f| (f.to_string(), 0.5))
+            .collect();
+        let scores = HashMap::from([(0, 2.0), (2, 8.0), (3, 2.0)]);
+        let ordered = order_by_scores(candidates, &scores, 3);
+        let files: Vec<&str> = ordered.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(files, vec!["c.rs", "a.rs", "d.rs"]);
+        assert_eq!(ordered[0].1, 8.0);
+    }
+}
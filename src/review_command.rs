@@ -0,0 +1,352 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+
+use crate::ask_command::{self, AskError, OutputFormat, EXIT_LLM, EXIT_USAGE};
+use crate::cache::Cache;
+use crate::citations;
+use crate::documents;
+use crate::llm_api::LLMApi;
+use crate::llm_prompt::Prompt;
+use crate::project_index::{ProjectIndex, Retrieval};
+use crate::utils::estimate_tokens;
+use crate::VERBOSE;
+
+static RE_HUNK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^@@ -\d+(?:,\d+)? \+(\d+)(?:,\d+)? @@").unwrap());
+// "src/main.rs:42: comment", also with list markers and backticks around the location
+static RE_COMMENT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?m)^[ \t]*(?:[-*][ \t]*)?`?([^\s`:]+):(\d+)(?:-\d+)?`?:?[ \t]+(.+)$").unwrap()
+});
+
+const REVIEW_PROMPT: &str = "Review the change above as a senior developer. Use the related code of the project to spot bugs, broken callers, missing error handling and unclear code in the added lines (marked with +). Reply with one line per issue in the form \"path:line: comment\" using the line numbers shown, and nothing else. Reply with \"LGTM\" if there is nothing to fix.";
+
+pub struct ReviewOptions {
+    // Passed to "git diff", e.g. "main..HEAD"; None reviews the uncommitted changes
+    pub range: Option<String>,
+    // Related files from the index shown with each hunk
+    pub top_k: usize,
+    pub context_tokens: usize,
+    pub format: OutputFormat,
+}
+
+// One "@@" block of the diff
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    // Relative to the reviewed folder
+    file: String,
+    // First and last line of the hunk in the new version of the file
+    start: usize,
+    end: usize,
+    // The hunk as shown to the LLM: new line numbers and the +/- markers
+    numbered: String,
+    // Added and removed lines, what retrieval looks for
+    changed: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReviewComment {
+    pub file: String,
+    pub line: usize,
+    pub comment: String,
+}
+
+#[derive(Serialize)]
+struct JsonReview<'a> {
+    range: Option<&'a str>,
+    hunks: usize,
+    comments: &'a [ReviewComment],
+}
+
+fn git_diff(path: &str, range: Option<&str>) -> Result<String, AskError> {
+    let mut command = Command::new("git");
+    // --relative: paths relative to `path`, like the ids of its index
+    command.args([
+        "-C",
+        path,
+        "diff",
+        "--no-color",
+        "--no-prefix",
+        "--relative",
+    ]);
+    command.arg(range.unwrap_or("HEAD"));
+    let output = command
+        .output()
+        .map_err(|e| AskError::new(1, format!("Cant run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(AskError::new(
+            EXIT_USAGE,
+            format!(
+                "git diff failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Hunks of a unified diff made with --no-prefix. Deleted files have nothing to comment on.
+fn parse_diff(diff: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut file: Option<String> = None;
+    let mut current: Option<(Hunk, usize)> = None;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            hunks.extend(current.take().map(|(h, _)| h));
+            file = None;
+            continue;
+        }
+        // Inside a hunk "+++ x" is the added line "++ x"
+        if let (None, Some(new_file)) = (&current, line.strip_prefix("+++ ")) {
+            file = (new_file != "/dev/null").then(|| new_file.to_string());
+            continue;
+        }
+        if let Some(cap) = RE_HUNK.captures(line) {
+            hunks.extend(current.take().map(|(h, _)| h));
+            let Some(file) = &file else {
+                continue;
+            };
+            let start: usize = cap[1].parse().unwrap_or(1);
+            current = Some((
+                Hunk {
+                    file: file.clone(),
+                    start,
+                    end: start,
+                    numbered: String::new(),
+                    changed: String::new(),
+                },
+                start,
+            ));
+            continue;
+        }
+        let Some((hunk, next_line)) = current.as_mut() else {
+            continue;
+        };
+        let (marker, text) = match line.chars().next() {
+            Some(c @ ('+' | '-' | ' ')) => (c, &line[1..]),
+            // "\ No newline at end of file"
+            _ => continue,
+        };
+        let number = match marker {
+            '-' => String::new(),
+            _ => {
+                hunk.end = *next_line;
+                *next_line += 1;
+                (*next_line - 1).to_string()
+            }
+        };
+        hunk.numbered
+            .push_str(&format!("{:>5}|{}{}\r\n", number, marker, text));
+        if marker != ' ' {
+            hunk.changed.push_str(text);
+            hunk.changed.push('\n');
+        }
+    }
+    hunks.extend(current.take().map(|(h, _)| h));
+    hunks
+}
+
+// Comments on the lines of the hunk, the rest would point at code the LLM did not see
+fn parse_comments(response: &str, hunk: &Hunk) -> Vec<ReviewComment> {
+    RE_COMMENT
+        .captures_iter(response)
+        .filter_map(|cap| {
+            let file = cap[1].trim_start_matches("./");
+            let line: usize = cap[2].parse().ok()?;
+            (file == hunk.file && line >= hunk.start && line <= hunk.end).then(|| ReviewComment {
+                file: hunk.file.clone(),
+                line,
+                comment: cap[3].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+// What every hunk is reviewed with
+struct Reviewer<'a> {
+    path: &'a str,
+    index: ProjectIndex,
+    retrieval: Retrieval,
+    options: &'a ReviewOptions,
+    llm: &'a LLMApi,
+    prompt: &'a Prompt,
+}
+
+impl Reviewer<'_> {
+    fn review(&self, hunk: &Hunk, cache: &mut Cache) -> Result<Vec<ReviewComment>, AskError> {
+        let related = self.related_code(hunk, cache);
+        let prompt_template = format!(
+            "Related code of the project:\r\n{}\r\n# Change in {}\r\n{}\r\n{}",
+            related, hunk.file, hunk.numbered, REVIEW_PROMPT
+        );
+        if *VERBOSE.lock().unwrap() {
+            info!("Request: {}", prompt_template);
+        }
+        let response = self
+            .llm
+            .try_request(&prompt_template, &Vec::new(), cache, self.prompt)
+            .map_err(|e| AskError::new(EXIT_LLM, e))?;
+        Ok(parse_comments(&response, hunk))
+    }
+
+    // Closest files of the index to the changed lines, other than the changed file
+    fn related_code(&self, hunk: &Hunk, cache: &mut Cache) -> String {
+        let file_id = Path::new(self.path).join(&hunk.file);
+        let query = format!("{}\n{}", hunk.file, hunk.changed);
+        let emb = if self.retrieval.uses_embeddings() {
+            self.llm.emb(&query, cache, &query)
+        } else {
+            vec![]
+        };
+        let question_emb =
+            (!emb.is_empty() && emb.len() == self.index.dimensions()).then_some(emb.as_slice());
+        let found = self.index.search(
+            &query,
+            question_emb,
+            self.retrieval,
+            self.options.top_k + 1,
+            &|_| true,
+        );
+        let mut budget = self.options.context_tokens;
+        let mut snippets = Vec::new();
+        for (id, _) in found
+            .iter()
+            .filter(|(id, _)| Path::new(documents::id_file(id)) != file_id)
+            .take(self.options.top_k)
+        {
+            let Some((path, start, content)) = documents::read_source(id) else {
+                continue;
+            };
+            let snippet = format!("# {}\r\n{}", path, citations::number_lines(&content, start));
+            let tokens = estimate_tokens(&snippet);
+            if tokens > budget {
+                continue;
+            }
+            budget -= tokens;
+            if *VERBOSE.lock().unwrap() {
+                info!("Related to {}:{}: {}", hunk.file, hunk.start, id);
+            }
+            snippets.push(snippet);
+        }
+        snippets.join("\r\n")
+    }
+}
+
+pub fn handle_review_command(
+    path: &str,
+    options: &ReviewOptions,
+    llm: &LLMApi,
+    cache: &mut Cache,
+    prompt: &Prompt,
+) -> Result<(), AskError> {
+    let diff = git_diff(path, options.range.as_deref())?;
+    let hunks = parse_diff(&diff);
+    let mut comments = Vec::new();
+    if !hunks.is_empty() {
+        let (index, retrieval) = ask_command::load_index(path, llm, Retrieval::Hybrid)?;
+        let reviewer = Reviewer {
+            path,
+            index,
+            retrieval,
+            options,
+            llm,
+            prompt,
+        };
+        for (n, hunk) in hunks.iter().enumerate() {
+            info!(
+                "Review {}:{} ({}/{})",
+                hunk.file,
+                hunk.start,
+                n + 1,
+                hunks.len()
+            );
+            comments.append(&mut reviewer.review(hunk, cache)?);
+        }
+    }
+    match options.format {
+        OutputFormat::Json => {
+            let output = JsonReview {
+                range: options.range.as_deref(),
+                hunks: hunks.len(),
+                comments: &comments,
+            };
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        }
+        OutputFormat::Text if hunks.is_empty() => println!("No changes to review"),
+        OutputFormat::Text if comments.is_empty() => println!("No comments"),
+        OutputFormat::Text => {
+            for c in &comments {
+                println!("{}:{}: {}", c.file, c.line, c.comment);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git src/cache.rs src/cache.rs
+index 1111111..2222222 100644
+--- src/cache.rs
++++ src/cache.rs
+@@ -10,4 +10,5 @@ impl Cache {
+     pub fn set(&mut self, key: String, value: String) {
+-        self.map.insert(key, value);
++        self.map.insert(key, value.clone());
++        self.save().unwrap();
+     }
+diff --git src/old.rs src/old.rs
+deleted file mode 100644
+--- src/old.rs
++++ /dev/null
+@@ -1,2 +0,0 @@
+-fn old() {
+-}
+diff --git README.md README.md
+--- README.md
++++ README.md
+@@ -3,0 +4 @@
++New line
+\\ No newline at end of file
+";
+
+    #[test]
+    fn test_parse_diff() {
+        let hunks = parse_diff(DIFF);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            (hunks[0].file.as_str(), hunks[0].start, hunks[0].end),
+            ("src/cache.rs", 10, 13)
+        );
+        assert!(hunks[0]
+            .numbered
+            .contains("     |-        self.map.insert(key, value);\r\n   11|+"));
+        assert_eq!(
+            hunks[0].changed,
+            "        self.map.insert(key, value);\n        self.map.insert(key, value.clone());\n        self.save().unwrap();\n"
+        );
+        assert_eq!(
+            (hunks[1].file.as_str(), hunks[1].start, hunks[1].end),
+            ("README.md", 4, 4)
+        );
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let hunks = parse_diff(DIFF);
+        let response = "- `src/cache.rs:12`: saving on every set is slow, save once in Drop\nsrc/cache.rs:40: outside of the hunk\nsrc/other.rs:11: another file\nLGTM";
+        let comments = parse_comments(response, &hunks[0]);
+        assert_eq!(
+            comments,
+            vec![ReviewComment {
+                file: "src/cache.rs".to_string(),
+                line: 12,
+                comment: "saving on every set is slow, save once in Drop".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::events::{Event, EventKind, EventSink};
+use crate::llm_response::{Project, ProjectFile};
+use crate::Lang;
+
+const SESSIONS_DIR: &str = ".rustsn/sessions";
+
+// manifest.json of a session: what was asked and how the run ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionManifest {
+    pub id: String,
+    pub lang: String,
+    // "function" or "application"
+    pub target: String,
+    // The file of "generate function --into"
+    pub into: Option<String>,
+    pub question: String,
+    pub created_ms: u128,
+    // "running", "succeeded" or "failed"
+    pub status: String,
+    pub reason: Option<String>,
+    // Rewrites of all the runs of the session, resumed ones included
+    pub attempts: u32,
+}
+
+// attempt-N/manifest.json: the project the LLM answered with and what its checks gave
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AttemptManifest {
+    pub attempt: u32,
+    // The answer the files come from, e.g. "response-2.md"; None for the first attempt
+    // of a resumed run, which checks the files of the attempt before again
+    pub response: Option<String>,
+    // The candidate the run went on with, see "generate --candidates"
+    pub picked: Option<usize>,
+    pub files: Vec<String>,
+    pub install_command: Option<String>,
+    pub build_command: String,
+    pub test_command: String,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Check {
+    pub state: String,
+    pub success: bool,
+    // Next to the manifest, e.g. "build.txt"
+    pub output: String,
+}
+
+// A folder .rustsn/sessions/<id>/ with the manifest of the run and one folder per attempt:
+// attempt-N/files/ with the project, prompt.md with the prompt sent, response.md with the
+// raw LLM answer (response-K.md for candidate K), <state>.txt with the build, test and
+// lint output and manifest.json with the commands
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub dir: PathBuf,
+    pub manifest: SessionManifest,
+    // Attempt numbers of a resumed run go on after the last one of the earlier runs
+    pub first_attempt: u32,
+}
+
+impl Session {
+    pub fn create(
+        lang: &Lang,
+        target: &str,
+        into: Option<String>,
+        question: &str,
+    ) -> Result<Session, String> {
+        Session::create_in(Path::new(SESSIONS_DIR), lang, target, into, question)
+    }
+
+    // In `sessions` instead of SESSIONS_DIR
+    pub fn create_in(
+        sessions: &Path,
+        lang: &Lang,
+        target: &str,
+        into: Option<String>,
+        question: &str,
+    ) -> Result<Session, String> {
+        std::fs::create_dir_all(sessions)
+            .map_err(|e| format!("Cant create {}: {}", sessions.display(), e))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        // Runs of "batch" start in the same second, the first free number wins
+        let mut n = 0;
+        let (id, dir) = loop {
+            let id = format!("{}-{}", now.as_secs(), n);
+            let dir = sessions.join(&id);
+            match std::fs::create_dir(&dir) {
+                Ok(()) => break (id, dir),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(format!("Cant create {}: {}", dir.display(), e)),
+            }
+        };
+        let session = Session {
+            dir,
+            manifest: SessionManifest {
+                id,
+                lang: lang.to_string(),
+                target: target.to_string(),
+                into,
+                question: question.to_string(),
+                created_ms: now.as_millis(),
+                status: "running".to_string(),
+                reason: None,
+                attempts: 0,
+            },
+            first_attempt: 0,
+        };
+        session.save_manifest()?;
+        Ok(session)
+    }
+
+    pub fn open(id: &str) -> Result<Session, String> {
+        Session::open_in(Path::new(SESSIONS_DIR), id)
+    }
+
+    pub fn open_in(sessions: &Path, id: &str) -> Result<Session, String> {
+        let dir = sessions.join(id);
+        let manifest: SessionManifest = read_json(&dir.join("manifest.json"))?;
+        let first_attempt = last_attempt(&dir).map_or(0, |n| n + 1);
+        Ok(Session {
+            dir,
+            manifest,
+            first_attempt,
+        })
+    }
+
+    // Newest first
+    pub fn list() -> Vec<SessionManifest> {
+        Session::list_in(Path::new(SESSIONS_DIR))
+    }
+
+    pub fn list_in(sessions: &Path) -> Vec<SessionManifest> {
+        let Ok(entries) = std::fs::read_dir(sessions) else {
+            return Vec::new();
+        };
+        let mut sessions: Vec<SessionManifest> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| read_json(&e.path().join("manifest.json")).ok())
+            .collect();
+        sessions.sort_by_key(|s| Reverse(s.created_ms));
+        sessions
+    }
+
+    pub fn attempts(&self) -> Vec<AttemptManifest> {
+        let mut attempts = Vec::new();
+        for attempt in 0..=last_attempt(&self.dir).unwrap_or(0) {
+            if let Ok(manifest) = read_json(&self.attempt_dir(attempt).join("manifest.json")) {
+                attempts.push(manifest);
+            }
+        }
+        attempts
+    }
+
+    // The project of the last attempt, what a resumed run goes on from
+    pub fn last_project(&self, lang: Lang) -> Result<Project, String> {
+        let Some(attempt) = self.attempts().pop() else {
+            return Err(format!("Session {} has no attempt", self.manifest.id));
+        };
+        let dir = self.attempt_dir(attempt.attempt).join("files");
+        let mut files = Vec::new();
+        for path in &attempt.files {
+            let content = std::fs::read_to_string(dir.join(path))
+                .map_err(|e| format!("Cant read {}: {}", dir.join(path).display(), e))?;
+            files.push(ProjectFile::new(path, content));
+        }
+        Ok(Project {
+            files,
+            install_dependency_command: attempt.install_command,
+            build_command: attempt.build_command,
+            test_command: attempt.test_command,
+            lang,
+            ..Default::default()
+        })
+    }
+
+    // For "session show"
+    pub fn print(&self) {
+        let m = &self.manifest;
+        println!("Session {} in {}", m.id, self.dir.display());
+        println!(
+            "{} {} {}",
+            m.lang,
+            m.target,
+            m.into.as_deref().unwrap_or("")
+        );
+        println!("Question: {}", m.question);
+        match &m.reason {
+            Some(reason) => println!("Status: {} ({}), {} rewrites", m.status, reason, m.attempts),
+            None => println!("Status: {}, {} rewrites", m.status, m.attempts),
+        }
+        for attempt in self.attempts() {
+            println!();
+            println!("attempt-{}: {}", attempt.attempt, attempt.files.join(", "));
+            match (&attempt.response, attempt.picked) {
+                (Some(response), Some(index)) => {
+                    println!("  {:<8} {} (candidate {})", "answer", response, index)
+                }
+                (Some(response), None) => println!("  {:<8} {}", "answer", response),
+                (None, _) => println!("  {:<8} files of the attempt before", "answer"),
+            }
+            for (name, command) in [
+                ("install", attempt.install_command.as_deref().unwrap_or("")),
+                ("build", attempt.build_command.as_str()),
+                ("test", attempt.test_command.as_str()),
+            ] {
+                if !command.is_empty() {
+                    println!("  {:<8} {}", name, command.trim());
+                }
+            }
+            for check in &attempt.checks {
+                let result = if check.success { "passed" } else { "failed" };
+                println!("  {:<8} {} ({})", check.state, result, check.output);
+            }
+        }
+    }
+
+    pub fn attempt_dir(&self, attempt: u32) -> PathBuf {
+        self.dir.join(format!("attempt-{}", attempt))
+    }
+
+    fn save_manifest(&self) -> Result<(), String> {
+        write_json(&self.dir.join("manifest.json"), &self.manifest)
+    }
+}
+
+// Highest N of the attempt-N folders
+fn last_attempt(dir: &Path) -> Option<u32> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_prefix("attempt-")?.parse().ok()
+        })
+        .max()
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cant read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content).map_err(|e| format!("Cant parse {}: {}", path.display(), e))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    std::fs::write(path, serde_json::to_string_pretty(value).unwrap())
+        .map_err(|e| format!("Cant write {}: {}", path.display(), e))
+}
+
+// Writes the attempts of a run to its session as the events come
+pub struct SessionSink {
+    session: Session,
+    // Manifest of the attempt the next files belong to
+    current: AttemptManifest,
+}
+
+impl SessionSink {
+    pub fn new(session: Session) -> SessionSink {
+        let current = AttemptManifest {
+            attempt: session.first_attempt,
+            ..Default::default()
+        };
+        SessionSink { session, current }
+    }
+
+    fn write(&mut self, event: &Event) -> Result<(), String> {
+        if let EventKind::Requested { attempt, .. } = &event.kind {
+            let attempt = self.session.first_attempt + attempt;
+            if attempt != self.current.attempt {
+                self.current = AttemptManifest {
+                    attempt,
+                    ..Default::default()
+                };
+            }
+        }
+        let dir = self.session.attempt_dir(self.current.attempt);
+        let write = |name: &str, content: &str| {
+            std::fs::create_dir_all(&dir)
+                .and_then(|_| std::fs::write(dir.join(name), content))
+                .map_err(|e| format!("Cant write {}: {}", dir.join(name).display(), e))
+        };
+        match &event.kind {
+            EventKind::Requested {
+                candidate,
+                request,
+                response,
+                ..
+            } => {
+                // The candidates share the prompt
+                write("prompt.md", request)?;
+                let name = response_name(*candidate);
+                write(&name, response)?;
+                self.current.response = Some(name);
+                Ok(())
+            }
+            EventKind::Picked { index } => {
+                self.current.response = Some(response_name(Some(*index)));
+                self.current.picked = Some(*index);
+                Ok(())
+            }
+            EventKind::Materialized {
+                files,
+                install_command,
+                build_command,
+                test_command,
+            } => {
+                crate::toolchain::write_files(&dir.join("files"), files)?;
+                self.current.files = files.iter().map(|f| f.path.clone()).collect();
+                self.current.install_command = install_command.clone();
+                self.current.build_command = build_command.clone();
+                self.current.test_command = test_command.clone();
+                write_json(&dir.join("manifest.json"), &self.current)
+            }
+            EventKind::Checked {
+                state,
+                success,
+                output,
+            } => {
+                let name = format!("{}.txt", state);
+                write(&name, output)?;
+                self.current.checks.push(Check {
+                    state: state.to_string(),
+                    success: *success,
+                    output: name,
+                });
+                write_json(&dir.join("manifest.json"), &self.current)
+            }
+            EventKind::Finished {
+                success,
+                reason,
+                attempts,
+            } => {
+                let manifest = &mut self.session.manifest;
+                manifest.status = if *success { "succeeded" } else { "failed" }.to_string();
+                manifest.reason = reason.clone();
+                manifest.attempts += attempts;
+                self.session.save_manifest()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn response_name(candidate: Option<usize>) -> String {
+    match candidate {
+        None | Some(0) => "response.md".to_string(),
+        Some(index) => format!("response-{}.md", index),
+    }
+}
+
+impl EventSink for SessionSink {
+    fn on_event(&mut self, event: &Event) {
+        if let Err(e) = self.write(event) {
+            eprintln!("Cant save the session: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: EventKind) -> Event {
+        Event {
+            at_ms: 0,
+            elapsed_ms: 0,
+            kind,
+        }
+    }
+
+    fn materialized(content: &str) -> EventKind {
+        EventKind::Materialized {
+            files: vec![ProjectFile::new("solution.py", content.to_string())],
+            install_command: None,
+            build_command: String::new(),
+            test_command: "python -m unittest".to_string(),
+        }
+    }
+
+    fn checked(output: &str) -> EventKind {
+        EventKind::Checked {
+            state: "test",
+            success: false,
+            output: output.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let sessions = std::env::temp_dir().join("rustsn_test_session");
+        let _ = std::fs::remove_dir_all(&sessions);
+        let mut session =
+            Session::create_in(&sessions, &Lang::Python, "function", None, "add").unwrap();
+        let id = session.manifest.id.clone();
+        let mut sink = SessionSink::new(session.clone());
+        let requested = |attempt, candidate: Option<usize>| EventKind::Requested {
+            prompt: "generate",
+            attempt,
+            candidate,
+            tokens: 10,
+            request: format!("prompt {}", attempt),
+            response: format!("answer {} {:?}", attempt, candidate),
+        };
+        // Two candidates, the second one goes on, then a rewrite
+        sink.on_event(&event(requested(0, Some(0))));
+        sink.on_event(&event(requested(0, Some(1))));
+        sink.on_event(&event(EventKind::Picked { index: 1 }));
+        sink.on_event(&event(materialized("v0")));
+        sink.on_event(&event(checked("FAILED (failures=2)")));
+        sink.on_event(&event(requested(1, None)));
+        sink.on_event(&event(materialized("v1")));
+        sink.on_event(&event(checked("FAILED (failures=1)")));
+        sink.on_event(&event(EventKind::Finished {
+            success: false,
+            reason: Some("too many attempts".to_string()),
+            attempts: 1,
+        }));
+
+        session = Session::open_in(&sessions, &id).unwrap();
+        assert_eq!(session.first_attempt, 2);
+        assert_eq!(session.manifest.status, "failed");
+        assert_eq!(session.manifest.attempts, 1);
+        let attempts = session.attempts();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].response.as_deref(), Some("response-1.md"));
+        assert_eq!(attempts[0].picked, Some(1));
+        assert_eq!(attempts[1].response.as_deref(), Some("response.md"));
+        assert_eq!(attempts[1].checks[0].output, "test.txt");
+        let read = |attempt, name| std::fs::read_to_string(session.attempt_dir(attempt).join(name));
+        assert_eq!(read(0, "response-1.md").unwrap(), "answer 0 Some(1)");
+        assert_eq!(read(1, "prompt.md").unwrap(), "prompt 1");
+        let project = session.last_project(Lang::Python).unwrap();
+        assert_eq!(project.file("solution.py"), "v1");
+        assert_eq!(project.test_command, "python -m unittest");
+        assert!(Session::list_in(&sessions).iter().any(|s| s.id == id));
+
+        // A resumed run checks the files again in a new attempt, the old outputs stay
+        let mut sink = SessionSink::new(session.clone());
+        sink.on_event(&event(materialized("v1")));
+        sink.on_event(&event(checked("FAILED (errors=1)")));
+        let attempts = session.attempts();
+        assert_eq!(attempts.len(), 3);
+        assert_eq!(attempts[2].response, None);
+        assert_eq!(read(1, "test.txt").unwrap(), "FAILED (failures=1)");
+        assert_eq!(read(2, "test.txt").unwrap(), "FAILED (errors=1)");
+        let _ = std::fs::remove_dir_all(&sessions);
+    }
+}
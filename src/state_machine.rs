@@ -1,15 +1,124 @@
-use crate::build_tool::{
-    build_tool, create_project_java, create_project_javascript, create_project_kotlin,
-    create_project_php, create_project_python, create_project_rust, create_project_scala,
-    create_project_swift, create_project_typescript,
-};
+use std::path::{Path, PathBuf};
+
+use crate::ask_command::{AskError, EXIT_USAGE};
 use crate::cache::Cache;
+use crate::candidates;
+use crate::events::{ConsoleSink, EventKind, EventLog, EventSink, JsonLinesSink, Report};
+use crate::generate_into::IntoTarget;
 use crate::llm_api::LLMApi;
 use crate::llm_prompt::Prompt;
-use crate::llm_response::LLMResponse;
-use crate::{Lang, MAX_NUMBER_OF_ATTEMPTS, VERBOSE};
+use crate::llm_response::{LLMResponse, Project};
+use crate::session::{Session, SessionSink};
+use crate::toolchain::{self, Toolchain};
+use crate::utils::estimate_tokens;
+use crate::{Lang, MAX_NUMBER_OF_ATTEMPTS};
+
+// Exit codes of "generate" and "batch", besides 0 and the usage code 2 shared with "ask"
+// No code passing the build and the tests, within MAX_NUMBER_OF_ATTEMPTS rewrites
+pub const EXIT_ATTEMPTS: i32 = 3;
+// The LLM, docker or the file system failed
+pub const EXIT_INFRA: i32 = 4;
+
+const TOO_MANY_ATTEMPTS: &str = "too many attempts";
+pub const NO_FILES: &str = "the LLM response has no files";
+
+// States of a "generate" run. Every run starts in Generate and ends in Succeeded or Failed:
+// Generate -> Materialize -> Build -> Test -> Lint -> Succeeded
+// and from Test or Lint on errors: Rewrite -> Materialize, until the attempts run out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    // Ask the LLM for the first version of the project
+    Generate,
+    // Write the project to the sandbox
+    Materialize,
+    Build,
+    Test,
+    Lint,
+    // Ask the LLM to fix the project with the build and test output
+    Rewrite,
+    Succeeded,
+    Failed(String),
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Generate => "generate",
+            State::Materialize => "materialize",
+            State::Build => "build",
+            State::Test => "test",
+            State::Lint => "lint",
+            State::Rewrite => "rewrite",
+            State::Succeeded => "succeeded",
+            State::Failed(_) => "failed",
+        }
+    }
+}
+
+// What "generate" makes
+#[derive(Debug)]
+pub enum Target {
+    // One function and its tests, laid out by the toolchain
+    Function,
+    // A project of any number of files chosen by the LLM, asked with prompt/application.txt
+    Application,
+    // A function and its tests added to a file of an existing project, asked with prompt/into.txt
+    Into(IntoTarget),
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Function | Target::Into(_) => write!(f, "function"),
+            Target::Application => write!(f, "application"),
+        }
+    }
+}
+
+pub struct RunOptions {
+    pub target: Target,
+    // JSON lines file receiving the events as they happen
+    pub events: Option<String>,
+    // Folder the generated files are written to once they pass
+    pub output_dir: Option<String>,
+    // Where the project is built and tested, toolchain::SANDBOX_DIR or a folder below it
+    pub sandbox: PathBuf,
+    // Print the progress, off for the parallel tasks of "batch"
+    pub console: bool,
+    // First generations built and tested side by side, the best one goes on; 1 for one
+    pub candidates: usize,
+    // Rewrites before giving up, set by "session resume --attempts"
+    pub max_attempts: u32,
+    // Session folder the attempts are saved to
+    pub session: Option<Session>,
+    // Project of an earlier run to build and test instead of asking the LLM for a new one
+    pub resume: Option<Project>,
+}
+
+impl RunOptions {
+    pub fn new(target: Target) -> RunOptions {
+        RunOptions {
+            target,
+            events: None,
+            output_dir: None,
+            sandbox: PathBuf::from(toolchain::SANDBOX_DIR),
+            console: true,
+            candidates: 1,
+            // MAX_NUMBER_OF_ATTEMPTS is the number of the last rewrite, counted from 0
+            max_attempts: MAX_NUMBER_OF_ATTEMPTS as u32 + 1,
+            session: None,
+            resume: None,
+        }
+    }
+}
 
-use crate::docker_tool;
+pub fn exit_code(report: &Report) -> i32 {
+    match report.reason.as_deref() {
+        _ if report.success => 0,
+        Some(TOO_MANY_ATTEMPTS) => EXIT_ATTEMPTS,
+        _ => EXIT_INFRA,
+    }
+}
 
 pub fn run_state_machine(
     lang: &Lang,
@@ -17,523 +126,448 @@ pub fn run_state_machine(
     prompt: &Prompt,
     cache: &mut Cache,
     llm: &LLMApi,
-) {
-    match lang {
-        Lang::Rust => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Rust);
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
-            }
-            create_project_rust(lang, &project);
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-            if build_res.0 && test_res.0 {
-                docker_tool::stop_container (lang);
-                docker_tool::remove_container (lang);
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_code,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Rust);
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-                    create_project_rust(lang, &project);
-                    build_res = build_tool(lang, &project.build_command, cache);
-                    test_res = build_tool(lang, &project.test_command, cache);
-                    if build_res.0 && test_res.0 {
-                        docker_tool::stop_container (lang);
-                        docker_tool::remove_container (lang);
-                        return;
-                    }
-                }
-            }
-        }
-        Lang::Java => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Java);
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
-            }
-            create_project_java(&project);
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Java);
-
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-
-                    create_project_java(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
+    options: &RunOptions,
+) -> Result<(Report, Project), AskError> {
+    let Some(toolchain) = toolchain::toolchain(lang) else {
+        return Err(AskError::new(EXIT_USAGE, toolchain::unsupported(lang)));
+    };
+    let mut sinks: Vec<Box<dyn EventSink>> = Vec::new();
+    if options.console {
+        sinks.push(Box::new(ConsoleSink));
+    }
+    if let Some(path) = &options.events {
+        let sink = JsonLinesSink::create(path).map_err(|e| AskError::new(EXIT_INFRA, e))?;
+        sinks.push(Box::new(sink));
+    }
+    if let Some(session) = &options.session {
+        sinks.push(Box::new(SessionSink::new(session.clone())));
+    }
+    let mut driver = Driver {
+        toolchain: toolchain.as_ref(),
+        target: &options.target,
+        output_dir: options.output_dir.as_deref(),
+        sandbox: &options.sandbox,
+        candidates: options.candidates,
+        max_attempts: options.max_attempts,
+        question,
+        prompt,
+        llm,
+        log: EventLog::new(sinks),
+        project: options.resume.clone().unwrap_or_default(),
+        attempt: 0,
+        build_output: None,
+        test_output: String::new(),
+        base_copied: false,
+    };
+    let start = match options.resume {
+        Some(_) => State::Materialize,
+        None => State::Generate,
+    };
+    driver.run(start, cache);
+    // The last version of the project, whether it passed or not
+    Ok((Report::from_events(driver.log.events()), driver.project))
+}
 
-                    test_res = build_tool(lang, &project.test_command, cache);
+// Runs the transitions one at a time and reports each of them to the event log
+struct Driver<'a> {
+    toolchain: &'a dyn Toolchain,
+    target: &'a Target,
+    output_dir: Option<&'a str>,
+    sandbox: &'a Path,
+    candidates: usize,
+    max_attempts: u32,
+    question: &'a str,
+    prompt: &'a Prompt,
+    llm: &'a LLMApi,
+    log: EventLog,
+    project: Project,
+    // Rewrites done so far
+    attempt: u32,
+    // Errors of the last build, None when it passed
+    build_output: Option<String>,
+    test_output: String,
+    // The project of --into was copied to the sandbox, later attempts only update it
+    base_copied: bool,
+}
 
-                    if build_res.0 && test_res.0 {
-                        return;
-                    }
-                }
+impl Driver<'_> {
+    fn run(&mut self, start: State, cache: &mut Cache) {
+        let mut state = start;
+        loop {
+            self.log.emit(EventKind::Entered {
+                state: state.name(),
+                attempt: self.attempt,
+            });
+            match state {
+                State::Succeeded | State::Failed(_) => break,
+                _ => state = self.step(&state, cache),
             }
         }
-        Lang::Scala => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Scala);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
+        if state == State::Succeeded {
+            if let Err(e) = self.apply() {
+                state = State::Failed(e);
             }
+        }
+        let reason = match state {
+            State::Failed(reason) => Some(reason),
+            _ => None,
+        };
+        self.log.emit(EventKind::Finished {
+            success: reason.is_none(),
+            reason,
+            attempts: self.attempt,
+        });
+    }
 
-            create_project_scala(&project);
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Scala);
-
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-
-                    create_project_scala(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
-
-                    test_res = build_tool(lang, &project.test_command, cache);
-
-                    if build_res.0 && test_res.0 {
-                        return;
+    // Does the work of `state` and returns the next one
+    fn step(&mut self, state: &State, cache: &mut Cache) -> State {
+        match state {
+            State::Generate => {
+                let params = match self.target {
+                    Target::Function => vec![self.question.to_string()],
+                    Target::Application => vec![
+                        self.toolchain.lang().to_string(),
+                        self.toolchain.application_hint().to_string(),
+                        self.question.to_string(),
+                    ],
+                    Target::Into(into) => {
+                        into.generate_params(&self.toolchain.lang(), self.question)
                     }
+                };
+                if self.candidates > 1 {
+                    self.generate_candidates(params, cache)
+                } else {
+                    self.request("generate", params, cache)
                 }
             }
-        }
-        Lang::Swift => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Swift);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
-            }
-
-            create_project_swift(&project);
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Swift);
-
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-
-                    create_project_swift(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
-
-                    test_res = build_tool(lang, &project.test_command, cache);
-
-                    if build_res.0 && test_res.0 {
-                        return;
+            State::Materialize => {
+                if self.project.files.is_empty() {
+                    return self.no_files();
+                }
+                let created = match self.target {
+                    Target::Into(into) => {
+                        let missing = into.missing_definitions(&self.project);
+                        if !missing.is_empty() {
+                            return self.rejected(format!(
+                                "Error: the answer drops {} from {}. Keep the existing code of the file as it is.",
+                                missing.join(", "),
+                                into.file()
+                            ));
+                        }
+                        if self.base_copied {
+                            into.update_sandbox(&self.project, self.sandbox)
+                        } else {
+                            toolchain::create_project(&self.project, self.sandbox)
+                        }
                     }
+                    _ => toolchain::create_project(&self.project, self.sandbox),
+                };
+                if let Err(e) = created {
+                    return State::Failed(e);
                 }
+                self.base_copied = true;
+                self.log.emit(EventKind::Materialized {
+                    files: self.project.files.clone(),
+                    install_command: self.project.install_dependency_command.clone(),
+                    build_command: self.project.build_command.clone(),
+                    test_command: self.project.test_command.clone(),
+                });
+                State::Build
             }
-        }
-        Lang::Kotlin => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Kotlin);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
+            State::Build => {
+                let (success, output) =
+                    match self.toolchain.build(&self.project, self.sandbox, cache) {
+                        Ok(result) => result,
+                        Err(e) => return State::Failed(format!("Cant run the build: {}", e)),
+                    };
+                self.checked(state, success, &output);
+                self.build_output = (!success).then_some(output);
+                // Tested even when the build fails, the rewrite gets both outputs
+                State::Test
             }
-
-            create_project_kotlin(&project);
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Kotlin);
-
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-
-                    create_project_kotlin(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
-
-                    test_res = build_tool(lang, &project.test_command, cache);
-
-                    if build_res.0 && test_res.0 {
-                        return;
-                    }
+            State::Test => {
+                let (success, output) =
+                    match self.toolchain.test(&self.project, self.sandbox, cache) {
+                        Ok(result) => result,
+                        Err(e) => return State::Failed(format!("Cant run the tests: {}", e)),
+                    };
+                self.checked(state, success, &output);
+                self.test_output = output;
+                if success && self.build_output.is_none() {
+                    State::Lint
+                } else {
+                    State::Rewrite
                 }
             }
-        }
-        Lang::Python => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Python);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
-            }
-
-            create_project_python(&project);
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Python);
-
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-
-                    create_project_python(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
-
-                    test_res = build_tool(lang, &project.test_command, cache);
-
-                    if build_res.0 && test_res.0 {
-                        return;
-                    }
+            State::Lint => match self.toolchain.lint(&self.project, self.sandbox, cache) {
+                None => {
+                    self.log.emit(EventKind::Skipped {
+                        state: state.name(),
+                    });
+                    State::Succeeded
+                }
+                Some(Err(e)) => State::Failed(format!("Cant run the linter: {}", e)),
+                Some(Ok((true, output))) => {
+                    self.checked(state, true, &output);
+                    State::Succeeded
+                }
+                Some(Ok((false, output))) => {
+                    self.checked(state, false, &output);
+                    // The rewrite prompts have no place of their own for lint errors
+                    self.test_output = output;
+                    State::Rewrite
                 }
+            },
+            State::Rewrite => {
+                if self.attempt >= self.max_attempts {
+                    return State::Failed(TOO_MANY_ATTEMPTS.to_string());
+                }
+                let build_output = self.build_output.as_deref().unwrap_or("");
+                let params = match self.target {
+                    Target::Function => self.toolchain.rewrite_params(
+                        &self.project,
+                        build_output,
+                        &self.test_output,
+                        self.question,
+                    ),
+                    Target::Application => vec![
+                        self.toolchain.lang().to_string(),
+                        self.toolchain.application_hint().to_string(),
+                        files_listing(&self.project),
+                        self.project.build_command.clone(),
+                        build_output.to_string(),
+                        self.project.test_command.clone(),
+                        self.test_output.clone(),
+                        self.question.to_string(),
+                    ],
+                    Target::Into(into) => into.rewrite_params(
+                        &self.toolchain.lang(),
+                        &self.project,
+                        build_output,
+                        &self.test_output,
+                        self.question,
+                    ),
+                };
+                self.attempt += 1;
+                self.request("rewrite", params, cache)
             }
+            State::Succeeded | State::Failed(_) => state.clone(),
         }
-        Lang::JavaScript => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::JavaScript);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
-            }
-
-            if *VERBOSE.lock().unwrap() {
-                create_project_javascript(&project);
-            }
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::JavaScript);
-
-                    println!("{:#?}", project);
-
-                    create_project_javascript(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
+    }
 
-                    test_res = build_tool(lang, &project.test_command, cache);
+    // Writes the passing files to the project of --into or to --output-dir
+    fn apply(&mut self) -> Result<(), String> {
+        let files = match (self.target, self.output_dir) {
+            (Target::Into(into), _) => into.apply(&self.project)?,
+            (_, Some(dir)) => toolchain::write_files(Path::new(dir), &self.project.files)?,
+            _ => return Ok(()),
+        };
+        self.log.emit(EventKind::Applied { files });
+        Ok(())
+    }
 
-                    if build_res.0 && test_res.0 {
-                        return;
-                    }
+    fn request(&mut self, key: &'static str, params: Vec<String>, cache: &mut Cache) -> State {
+        match self.llm.try_request(key, &params, cache, self.prompt) {
+            Ok(response) => {
+                let request = self.prompt.create(key, &params);
+                self.log.emit(EventKind::Requested {
+                    prompt: key,
+                    attempt: self.attempt,
+                    candidate: None,
+                    tokens: tokens(&request, &response),
+                    request,
+                    response: response.clone(),
+                });
+                let project = parse(self.toolchain, self.target, &response);
+                if project.files.is_empty() {
+                    return self.no_files();
                 }
+                self.project = project;
+                State::Materialize
             }
+            Err(e) => State::Failed(format!("LLM request failed: {}", e)),
         }
-        Lang::TypeScript => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::TypeScript);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
-            }
-
-            create_project_typescript(&project);
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    let ts_config = project.additional_config[0].clone();
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
-
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            ts_config,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::TypeScript);
-
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
-
-                    create_project_typescript(&project);
-
-                    build_res = build_tool(lang, &project.build_command, cache);
-
-                    test_res = build_tool(lang, &project.test_command, cache);
+    }
 
-                    if build_res.0 && test_res.0 {
-                        return;
+    // Samples the first generation `candidates` times and builds and tests all of them;
+    // the run goes on with the best one, which builds from the cache in the sandbox
+    fn generate_candidates(&mut self, params: Vec<String>, cache: &mut Cache) -> State {
+        let (toolchain, target, llm, prompt) = (self.toolchain, self.target, self.llm, self.prompt);
+        // The seed is not part of the prompt, all the candidates were asked the same
+        let request = prompt.create("generate", &params);
+        let candidates = candidates::run(
+            self.candidates,
+            self.sandbox,
+            toolchain,
+            cache,
+            |index, cache| {
+                // The first candidate is the answer of a run without --candidates
+                let seed = (index > 0).then_some(index as u64);
+                let response = llm
+                    .try_request_seeded("generate", &params, cache, prompt, seed)
+                    .map_err(|e| format!("LLM request failed: {}", e))?;
+                let tokens = tokens(&request, &response);
+                Ok((parse(toolchain, target, &response), tokens, response))
+            },
+        );
+        for (index, candidate) in candidates.iter().enumerate() {
+            let event = match candidate {
+                Ok(c) => {
+                    self.log.emit(EventKind::Requested {
+                        prompt: "generate",
+                        attempt: self.attempt,
+                        candidate: Some(index),
+                        tokens: c.tokens,
+                        request: request.clone(),
+                        response: c.response.clone(),
+                    });
+                    EventKind::Candidate {
+                        index,
+                        build: c.build_output.is_none(),
+                        test: c.test_passed,
+                        passed_tests: c.passed_tests(),
+                        error: None,
                     }
                 }
-            }
+                Err(e) => EventKind::Candidate {
+                    index,
+                    build: false,
+                    test: false,
+                    passed_tests: 0,
+                    error: Some(e.clone()),
+                },
+            };
+            self.log.emit(event);
         }
-        Lang::Php => {
-            let result = llm.request("generate", &vec![question.to_string()], cache, prompt);
-            let mut project = LLMResponse::parse_llm_response(&result, Lang::Php);
-
-            if *VERBOSE.lock().unwrap() {
-                println!("{:#?}", project);
+        let Some(best) = candidates::pick(&candidates).map(|c| c.index) else {
+            // They all failed the same way as a single generation would
+            let error = candidates.into_iter().find_map(|c| c.err()).unwrap();
+            if error != NO_FILES {
+                return State::Failed(error);
             }
+            return self.no_files();
+        };
+        self.log.emit(EventKind::Picked { index: best });
+        self.project = candidates
+            .into_iter()
+            .find_map(|c| c.ok().filter(|c| c.index == best))
+            .unwrap()
+            .project;
+        State::Materialize
+    }
 
-            create_project_php(&project);
-
-            let mut build_res = build_tool(lang, &project.build_command, cache);
-
-            let mut test_res = build_tool(lang, &project.test_command, cache);
-
-            if build_res.0 && test_res.0 {
-                return;
-            } else {
-                let mut number_of_attempts = 0;
-                loop {
-                    if number_of_attempts > MAX_NUMBER_OF_ATTEMPTS {
-                        println!("To many attempts");
+    // An answer the files cannot be read from is a failed attempt like any other: the
+    // rewrite gets the error and the project before it, if any
+    fn no_files(&mut self) -> State {
+        self.rejected(format!(
+            "Error: {}. Answer with every file of the project in the format asked for.",
+            NO_FILES
+        ))
+    }
 
-                        break;
-                    }
-                    number_of_attempts += 1;
-                    let result = llm.request(
-                        "rewrite",
-                        &vec![
-                            project.dependencies,
-                            project.solution_code,
-                            project.test_code,
-                            project.build_command,
-                            build_res.1,
-                            project.test_command,
-                            test_res.1,
-                            question.to_string(),
-                        ],
-                        cache,
-                        prompt,
-                    );
-                    project = LLMResponse::parse_llm_response(&result, Lang::Php);
+    // An answer that cannot be built is a failed attempt, `output` tells the rewrite why
+    fn rejected(&mut self, output: String) -> State {
+        self.checked(&State::Materialize, false, &output);
+        self.build_output = None;
+        self.test_output = output;
+        State::Rewrite
+    }
 
-                    if *VERBOSE.lock().unwrap() {
-                        println!("{:#?}", project);
-                    }
+    fn checked(&mut self, state: &State, success: bool, output: &str) {
+        self.log.emit(EventKind::Checked {
+            state: state.name(),
+            success,
+            output: output.to_string(),
+        });
+    }
+}
 
-                    create_project_php(&project);
+fn parse(toolchain: &dyn Toolchain, target: &Target, response: &str) -> Project {
+    match target {
+        Target::Function => toolchain.parse(response),
+        Target::Application => LLMResponse::parse_files(response, toolchain.lang()),
+        Target::Into(into) => into.parse(response, toolchain.lang()),
+    }
+}
 
-                    build_res = build_tool(lang, &project.build_command, cache);
+// Estimated, cached answers count as well
+fn tokens(request: &str, response: &str) -> usize {
+    estimate_tokens(request) + estimate_tokens(response)
+}
 
-                    test_res = build_tool(lang, &project.test_command, cache);
+// The files of the project in the format the LLM answers with
+fn files_listing(project: &Project) -> String {
+    project
+        .files
+        .iter()
+        .map(|f| format!("**{}**\n```\n{}\n```\n", f.path, f.content.trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-                    if build_res.0 && test_res.0 {
-                        return;
-                    }
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let report = |success, reason: Option<&str>| Report {
+            success,
+            reason: reason.map(str::to_string),
+            attempts: 0,
+            tokens: 0,
+            duration_ms: 0,
+            states: Vec::new(),
+        };
+        assert_eq!(exit_code(&report(true, None)), 0);
+        assert_eq!(
+            exit_code(&report(false, Some(TOO_MANY_ATTEMPTS))),
+            EXIT_ATTEMPTS
+        );
+        assert_eq!(
+            exit_code(&report(false, Some("LLM request failed: timeout"))),
+            EXIT_INFRA
+        );
+    }
 
-        &_ => {
-            panic!("Unknown lang: {}", lang);
-        }
+    #[test]
+    fn test_no_files_and_max_attempts() {
+        assert_eq!(
+            RunOptions::new(Target::Function).max_attempts,
+            MAX_NUMBER_OF_ATTEMPTS as u32 + 1
+        );
+        let toolchain = toolchain::toolchain(&Lang::Python).unwrap();
+        let llm = LLMApi::new(crate::llm_api::ModelType::Ollama {
+            model: String::new(),
+            emb: String::new(),
+        });
+        let prompt = Prompt::default();
+        let mut driver = Driver {
+            toolchain: toolchain.as_ref(),
+            target: &Target::Function,
+            output_dir: None,
+            sandbox: Path::new(toolchain::SANDBOX_DIR),
+            candidates: 1,
+            max_attempts: 3,
+            question: "add two numbers",
+            prompt: &prompt,
+            llm: &llm,
+            log: EventLog::new(Vec::new()),
+            project: Project::default(),
+            attempt: 0,
+            build_output: None,
+            test_output: String::new(),
+            base_copied: false,
+        };
+        // An answer without files is rewritten while attempts are left
+        let state = driver.step(&State::Materialize, &mut Cache::default());
+        assert_eq!(state, State::Rewrite);
+        assert!(driver.test_output.contains(NO_FILES));
+
+        // Rewrites 0, 1 and 2 are done, the run stops before asking for a 4th one
+        driver.attempt = 3;
+        let state = driver.step(&State::Rewrite, &mut Cache::default());
+        assert_eq!(state, State::Failed(TOO_MANY_ATTEMPTS.to_string()));
+        assert_eq!(driver.attempt, 3);
     }
-    //docker_tool::stop_container (lang);
-    //docker_tool::remove_container (lang);
 }
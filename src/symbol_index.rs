@@ -0,0 +1,387 @@
+use bincode::{Decode, Encode};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+
+// A regex pass, not a parser: it finds top level items and methods written the
+// usual way and is good enough to pull related code into the "ask" context.
+static RE_RUST_DEF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|extern\s+"[^"]*")\s+)*(?:fn|struct|enum|trait|union|static|const)\s+([A-Za-z_]\w*)"#).unwrap()
+});
+// Type aliases only at the top level, associated types like "type Err" are noise
+static RE_RUST_TYPE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?:pub(?:\([^)]*\))?\s+)?type\s+([A-Za-z_]\w*)").unwrap());
+static RE_JS_DEF: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(?:function\*?|class|interface|type|enum)\s+([A-Za-z_$][\w$]*)").unwrap()
+});
+static RE_JS_ARROW: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(?:export\s+)?(?:const|let|var)\s+([A-Za-z_$][\w$]*)\s*(?::[^=]+)?=\s*(?:async\s+)?(?:function|\([^)]*\)\s*(?::[^=]+)?=>|[A-Za-z_$][\w$]*\s*=>)").unwrap()
+});
+static RE_JS_METHOD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s+(?:(?:public|private|protected|static|async|readonly|get|set)\s+)*([A-Za-z_$][\w$]*)\s*\([^)]*\)\s*(?::[^{]+)?\{").unwrap()
+});
+static RE_CS_TYPE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(?:(?:public|private|protected|internal|static|abstract|sealed|partial|readonly)\s+)*(?:class|interface|struct|enum|record)\s+([A-Za-z_]\w*)").unwrap()
+});
+static RE_CS_METHOD: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(?:(?:public|private|protected|internal|static|virtual|override|async|abstract|sealed|extern|unsafe|new)\s+)+[\w<>\[\],.?]+\s+([A-Za-z_]\w*)\s*\(").unwrap()
+});
+static RE_IDENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"[A-Za-z_$][\w$]*").unwrap());
+
+// Control flow words that look like calls to RE_JS_METHOD
+const NOT_METHODS: &[&str] = &[
+    "if", "for", "while", "switch", "catch", "return", "function",
+];
+// Names defined in more files than this ("new", "get", ...) say nothing about a relation
+const MAX_DEFINITIONS_PER_NAME: usize = 3;
+// A definition whose closing brace is not found is cut at this length
+const MAX_DEFINITION_LINES: usize = 200;
+// Lines shown around a reference that is not inside any definition
+const REFERENCE_CONTEXT_LINES: u32 = 3;
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    // 1 based, inclusive
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone)]
+struct FileSymbols {
+    definitions: Vec<Definition>,
+    // Identifiers of the file with the line they first appear on, once per
+    // enclosing definition. A definition does not reference its own name.
+    references: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Relation {
+    // Defined elsewhere and used by the retrieved file
+    Uses,
+    // Defined elsewhere and uses something of the retrieved file
+    UsedBy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelatedCode {
+    pub file: String,
+    pub start: u32,
+    pub end: u32,
+    pub name: String,
+    pub relation: Relation,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Default, Clone)]
+pub struct SymbolIndex {
+    files: HashMap<String, FileSymbols>,
+    // Name to the files defining it
+    definitions: HashMap<String, Vec<String>>,
+}
+
+impl SymbolIndex {
+    pub fn insert(&mut self, file: &str, content: &str) {
+        self.remove(file);
+        let Some(language) = language_of(file) else {
+            return;
+        };
+        let symbols = parse_file(content, language);
+        for definition in &symbols.definitions {
+            let files = self.definitions.entry(definition.name.clone()).or_default();
+            if !files.iter().any(|f| f == file) {
+                files.push(file.to_string());
+            }
+        }
+        self.files.insert(file.to_string(), symbols);
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        let Some(symbols) = self.files.remove(file) else {
+            return;
+        };
+        for definition in symbols.definitions {
+            if let Some(files) = self.definitions.get_mut(&definition.name) {
+                files.retain(|f| f != file);
+                if files.is_empty() {
+                    self.definitions.remove(&definition.name);
+                }
+            }
+        }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &String> {
+        self.files.keys()
+    }
+
+    // Definitions used by `file` and code using the definitions of `file`,
+    // most relevant first. Code of the `exclude` files is never returned.
+    pub fn related(&self, file: &str, exclude: &[String]) -> Vec<RelatedCode> {
+        let Some(symbols) = self.files.get(file) else {
+            return vec![];
+        };
+        let excluded = |f: &str| f == file || exclude.iter().any(|e| e == f);
+        let mut related = Vec::new();
+
+        for (name, _) in &symbols.references {
+            for other in self.defining_files(name) {
+                if excluded(other) {
+                    continue;
+                }
+                for definition in self.files[other]
+                    .definitions
+                    .iter()
+                    .filter(|d| &d.name == name)
+                {
+                    related.push(RelatedCode {
+                        file: other.clone(),
+                        start: definition.start,
+                        end: definition.end,
+                        name: name.clone(),
+                        relation: Relation::Uses,
+                    });
+                }
+            }
+        }
+
+        let own: HashSet<&str> = symbols
+            .definitions
+            .iter()
+            .filter(|d| !self.defining_files(&d.name).is_empty())
+            .map(|d| d.name.as_str())
+            .collect();
+        let mut others: Vec<&String> = self.files.keys().filter(|f| !excluded(f)).collect();
+        others.sort();
+        for other in others {
+            let other_symbols = &self.files[other];
+            for (name, line) in &other_symbols.references {
+                if !own.contains(name.as_str()) {
+                    continue;
+                }
+                let (start, end) = match enclosing_definition(&other_symbols.definitions, *line) {
+                    Some(definition) => (definition.start, definition.end),
+                    None => (
+                        line.saturating_sub(REFERENCE_CONTEXT_LINES).max(1),
+                        line + REFERENCE_CONTEXT_LINES,
+                    ),
+                };
+                related.push(RelatedCode {
+                    file: other.clone(),
+                    start,
+                    end,
+                    name: name.clone(),
+                    relation: Relation::UsedBy,
+                });
+            }
+        }
+
+        let mut seen = HashSet::new();
+        related.retain(|r| seen.insert((r.file.clone(), r.start, r.end)));
+        related
+    }
+
+    fn defining_files(&self, name: &str) -> &[String] {
+        match self.definitions.get(name) {
+            Some(files) if files.len() <= MAX_DEFINITIONS_PER_NAME => files,
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Language {
+    Rust,
+    JavaScript,
+    CSharp,
+}
+
+fn language_of(file: &str) -> Option<Language> {
+    let ext = file.rsplit('.').next()?;
+    match ext {
+        "rs" => Some(Language::Rust),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" => Some(Language::JavaScript),
+        "cs" => Some(Language::CSharp),
+        _ => None,
+    }
+}
+
+fn definition_name(line: &str, language: Language) -> Option<String> {
+    let patterns: &[&Regex] = match language {
+        Language::Rust => &[&RE_RUST_DEF, &RE_RUST_TYPE],
+        Language::JavaScript => &[&RE_JS_DEF, &RE_JS_ARROW, &RE_JS_METHOD],
+        Language::CSharp => &[&RE_CS_TYPE, &RE_CS_METHOD],
+    };
+    patterns
+        .iter()
+        .filter_map(|re| re.captures(line))
+        .map(|cap| cap[1].to_string())
+        .find(|name| !NOT_METHODS.contains(&name.as_str()))
+}
+
+fn parse_file(content: &str, language: Language) -> FileSymbols {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut symbols = FileSymbols::default();
+    let mut definition_lines = HashSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(name) = definition_name(line, language) {
+            let start = i as u32 + 1;
+            symbols.definitions.push(Definition {
+                name,
+                start,
+                end: definition_end(&lines, i) as u32 + 1,
+            });
+            definition_lines.insert(start);
+        }
+    }
+    let mut seen = HashSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        let line_number = i as u32 + 1;
+        let own_name = if definition_lines.contains(&line_number) {
+            definition_name(line, language)
+        } else {
+            None
+        };
+        let scope = enclosing_definition(&symbols.definitions, line_number).map(|d| d.start);
+        for ident in RE_IDENT.find_iter(line) {
+            let ident = ident.as_str();
+            if ident.len() < 3 || own_name.as_deref() == Some(ident) {
+                continue;
+            }
+            if seen.insert((ident.to_string(), scope)) {
+                symbols.references.push((ident.to_string(), line_number));
+            }
+        }
+    }
+    symbols
+}
+
+// Index of the line closing the definition that starts at `start`: the line
+// where its braces balance again, or the first line ending with ';' when the
+// item has no body.
+fn definition_end(lines: &[&str], start: usize) -> usize {
+    let mut depth = 0i32;
+    let mut opened = false;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        if i - start >= MAX_DEFINITION_LINES {
+            return i - 1;
+        }
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if opened && depth <= 0 {
+            return i;
+        }
+        if !opened && line.trim_end().ends_with(';') {
+            return i;
+        }
+    }
+    lines.len().saturating_sub(1).max(start)
+}
+
+// The innermost definition containing the line, e.g. the method rather than its class
+fn enclosing_definition(definitions: &[Definition], line: u32) -> Option<&Definition> {
+    definitions
+        .iter()
+        .filter(|d| d.start <= line && line <= d.end)
+        .min_by_key(|d| d.end - d.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CACHE_RS: &str = "use std::collections::HashMap;
+
+pub struct Cache {
+    cache: HashMap<String, String>,
+}
+
+impl Cache {
+    pub fn get(&mut self, key: &str) -> Option<&String> {
+        self.cache.get(key)
+    }
+}
+";
+
+    const MAIN_RS: &str = "fn main() {
+    let mut cache = Cache::new();
+    let answer = ask_llm(&mut cache);
+    println!(\"{}\", answer);
+}
+
+fn ask_llm(cache: &mut Cache) -> String {
+    cache.get(\"q\").cloned().unwrap_or_default()
+}
+";
+
+    #[test]
+    fn test_parse_rust_definitions() {
+        let symbols = parse_file(CACHE_RS, Language::Rust);
+        assert_eq!(
+            symbols.definitions,
+            vec![
+                Definition {
+                    name: "Cache".to_string(),
+                    start: 3,
+                    end: 5
+                },
+                Definition {
+                    name: "get".to_string(),
+                    start: 8,
+                    end: 10
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_js_and_cs_definitions() {
+        let js = "export async function loadUser(id) {\n  return api.get(id);\n}\nconst render = (user) => {\n  if (user) {\n    show(user);\n  }\n};\nclass View {\n  draw(ctx) {\n  }\n}\n";
+        let names: Vec<String> = parse_file(js, Language::JavaScript)
+            .definitions
+            .into_iter()
+            .map(|d| d.name)
+            .collect();
+        assert_eq!(names, vec!["loadUser", "render", "View", "draw"]);
+
+        let cs = "public class PdfParser\n{\n    public static Document Parse(byte[] data)\n    {\n        return new Document();\n    }\n}\n";
+        let definitions = parse_file(cs, Language::CSharp).definitions;
+        assert_eq!(definitions[0].name, "PdfParser");
+        assert_eq!((definitions[0].start, definitions[0].end), (1, 7));
+        assert_eq!(definitions[1].name, "Parse");
+        assert_eq!((definitions[1].start, definitions[1].end), (3, 6));
+    }
+
+    #[test]
+    fn test_related_finds_uses_and_callers() {
+        let mut index = SymbolIndex::default();
+        index.insert("src/cache.rs", CACHE_RS);
+        index.insert("src/main.rs", MAIN_RS);
+        index.insert("README.md", "Cache");
+
+        let uses = index.related("src/main.rs", &[]);
+        assert_eq!(uses[0].file, "src/cache.rs");
+        assert_eq!(uses[0].name, "Cache");
+        assert_eq!((uses[0].start, uses[0].end), (3, 5));
+        assert_eq!(uses[0].relation, Relation::Uses);
+
+        let callers = index.related("src/cache.rs", &[]);
+        assert!(callers.iter().all(|r| r.relation == Relation::UsedBy));
+        // main() and ask_llm() both use Cache
+        let ranges: Vec<(u32, u32)> = callers.iter().map(|r| (r.start, r.end)).collect();
+        assert!(ranges.contains(&(1, 5)));
+        assert!(ranges.contains(&(7, 9)));
+
+        index.remove("src/cache.rs");
+        assert!(index.related("src/main.rs", &[]).is_empty());
+        assert!(index
+            .related("src/main.rs", &["src/cache.rs".to_string()])
+            .is_empty());
+    }
+}
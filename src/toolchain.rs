@@ -0,0 +1,426 @@
+use std::path::Path;
+
+use crate::build_tool::build_tool;
+use crate::cache::Cache;
+use crate::generate_into;
+use crate::llm_response::{LLMResponse, Project, ProjectFile};
+use crate::Lang;
+
+// Folder of the generated project, built and tested by build_tool; "batch" runs
+// every task in a folder below it
+pub const SANDBOX_DIR: &str = "sandbox";
+
+// What differs between the languages of "generate": the files of a function project,
+// the parameters of the "rewrite" prompt and how an application is laid out. The
+// generate, build and rewrite loop is shared, see state_machine::run_state_machine.
+pub trait Toolchain: Sync {
+    fn lang(&self) -> Lang;
+
+    // Manifest, solution and test file of "generate function", relative to SANDBOX_DIR
+    fn function_files(&self) -> [&'static str; 3];
+
+    // Project layout and commands asked for by "generate application"
+    fn application_hint(&self) -> &'static str;
+
+    // Positional parameters of the "rewrite" prompt in prompt/<lang>.txt
+    fn rewrite_params(
+        &self,
+        project: &Project,
+        build_output: &str,
+        test_output: &str,
+        question: &str,
+    ) -> Vec<String> {
+        let [manifest, solution, test] = self.function_files();
+        vec![
+            project.file(manifest).to_string(),
+            project.file(solution).to_string(),
+            project.file(test).to_string(),
+            project.build_command.clone(),
+            build_output.to_string(),
+            project.test_command.clone(),
+            test_output.to_string(),
+            question.to_string(),
+        ]
+    }
+
+    fn parse(&self, response: &str) -> Project {
+        LLMResponse::parse_llm_response(response, self.lang())
+    }
+
+    // The dependencies are installed first when the LLM gave a command.
+    // Interpreted languages may have nothing to build.
+    fn build(&self, project: &Project, sandbox: &Path, cache: &mut Cache) -> Check {
+        let sources = project.sources();
+        if let Some(install) = &project.install_dependency_command {
+            if !install.trim().is_empty() {
+                let installed = build_tool(&self.lang(), install, sandbox, &sources, cache)?;
+                if !installed.0 {
+                    return Ok(installed);
+                }
+            }
+        }
+        if project.build_command.trim().is_empty() {
+            return Ok((true, String::new()));
+        }
+        build_tool(
+            &self.lang(),
+            &project.build_command,
+            sandbox,
+            &sources,
+            cache,
+        )
+    }
+
+    // Code without tests does not pass
+    fn test(&self, project: &Project, sandbox: &Path, cache: &mut Cache) -> Check {
+        if project.test_command.trim().is_empty() {
+            return Ok((false, NO_TEST_COMMAND.to_string()));
+        }
+        let sources = project.sources();
+        build_tool(
+            &self.lang(),
+            &project.test_command,
+            sandbox,
+            &sources,
+            cache,
+        )
+    }
+
+    // None when the language has no linter to run
+    fn lint(&self, _project: &Project, _sandbox: &Path, _cache: &mut Cache) -> Option<Check> {
+        None
+    }
+}
+
+// (success, error output) of a build, test or lint; Err when the command could not be
+// run at all, e.g. docker is down, which no rewrite can fix
+pub type Check = Result<(bool, String), String>;
+
+// The languages toolchain() has a toolchain for
+pub const GENERATE_LANGS: [Lang; 9] = [
+    Lang::Rust,
+    Lang::Java,
+    Lang::Scala,
+    Lang::Swift,
+    Lang::Kotlin,
+    Lang::Python,
+    Lang::JavaScript,
+    Lang::TypeScript,
+    Lang::Php,
+];
+
+// None for the languages "generate" does not support
+pub fn toolchain(lang: &Lang) -> Option<Box<dyn Toolchain>> {
+    match lang {
+        Lang::Rust => Some(Box::new(RustToolchain)),
+        Lang::Java => Some(Box::new(JavaToolchain)),
+        Lang::Scala => Some(Box::new(ScalaToolchain)),
+        Lang::Swift => Some(Box::new(SwiftToolchain)),
+        Lang::Kotlin => Some(Box::new(KotlinToolchain)),
+        Lang::Python => Some(Box::new(PythonToolchain)),
+        Lang::JavaScript => Some(Box::new(JavaScriptToolchain)),
+        Lang::TypeScript => Some(Box::new(TypeScriptToolchain)),
+        Lang::Php => Some(Box::new(PhpToolchain)),
+        _ => None,
+    }
+}
+
+pub fn unsupported(lang: &Lang) -> String {
+    let langs: Vec<String> = GENERATE_LANGS.iter().map(|l| l.to_string()).collect();
+    format!(
+        "Unsupported language for generate: {}, use one of {}",
+        lang,
+        langs.join(", ")
+    )
+}
+
+// Replaces `sandbox` with the files of `project`, over a copy of its base project if any
+pub fn create_project(project: &Project, sandbox: &Path) -> Result<(), String> {
+    if sandbox.exists() {
+        std::fs::remove_dir_all(sandbox)
+            .map_err(|e| format!("Cant remove {}: {}", sandbox.display(), e))?;
+    }
+    std::fs::create_dir_all(sandbox)
+        .map_err(|e| format!("Cant create {}: {}", sandbox.display(), e))?;
+    if let Some(base) = &project.base {
+        generate_into::copy_base(Path::new(base), &project.lang, sandbox)?;
+    }
+    write_files(sandbox, &project.files)?;
+    Ok(())
+}
+
+// Writes `files` under `dir`, creating the folders, returns the written paths
+pub fn write_files(dir: &Path, files: &[ProjectFile]) -> Result<Vec<String>, String> {
+    let mut written = Vec::new();
+    for file in files {
+        let path = dir.join(&file.path);
+        let write = std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| std::fs::write(&path, &file.content));
+        write.map_err(|e| format!("Cant write {}: {}", path.display(), e))?;
+        written.push(path.to_string_lossy().to_string());
+    }
+    Ok(written)
+}
+
+const NO_TEST_COMMAND: &str =
+    "Error: the answer has no test command. Give the tests and the command running them.";
+const RUST_LINT_COMMAND: &str = "cargo clippy --all-targets -- -D warnings";
+
+pub struct RustToolchain;
+
+impl Toolchain for RustToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Rust
+    }
+
+    // The tests are in lib.rs
+    fn function_files(&self) -> [&'static str; 3] {
+        ["Cargo.toml", "src/lib.rs", "src/lib.rs"]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Cargo binary crate: Cargo.toml, src/main.rs, one file per module in src/, unit tests in #[cfg(test)] modules. Build with \"cargo build --tests\", test with \"cargo test\"."
+    }
+
+    fn rewrite_params(
+        &self,
+        project: &Project,
+        build_output: &str,
+        test_output: &str,
+        question: &str,
+    ) -> Vec<String> {
+        vec![
+            project.file("Cargo.toml").to_string(),
+            project.file("src/lib.rs").to_string(),
+            project.build_command.clone(),
+            build_output.to_string(),
+            project.test_command.clone(),
+            test_output.to_string(),
+            question.to_string(),
+        ]
+    }
+
+    // Warnings of a project the code was generated into are not the LLM's to fix
+    fn lint(&self, project: &Project, sandbox: &Path, cache: &mut Cache) -> Option<Check> {
+        if project.base.is_some() {
+            return None;
+        }
+        let sources = project.sources();
+        Some(build_tool(
+            &Lang::Rust,
+            RUST_LINT_COMMAND,
+            sandbox,
+            &sources,
+            cache,
+        ))
+    }
+}
+
+pub struct JavaToolchain;
+
+impl Toolchain for JavaToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Java
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        [
+            "pom.xml",
+            "src/main/java/com/example/solution/Solution.java",
+            "src/test/java/com/example/solution/SolutionTest.java",
+        ]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Maven project: pom.xml with JUnit 5, classes in src/main/java/com/example/app/ with a Main class, tests in src/test/java/com/example/app/. Build with \"mvn compile\", test with \"mvn test\"."
+    }
+}
+
+pub struct ScalaToolchain;
+
+impl Toolchain for ScalaToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Scala
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        [
+            "build.sbt",
+            "src/main/scala/Solution.scala",
+            "src/test/scala/SolutionTest.scala",
+        ]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "an sbt project: build.sbt with ScalaTest, sources in src/main/scala/ with a Main object, tests in src/test/scala/. Build with \"sbt compile\", test with \"sbt test\"."
+    }
+}
+
+pub struct SwiftToolchain;
+
+impl Toolchain for SwiftToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Swift
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        [
+            "Package.swift",
+            "Sources/Solution/Solution.swift",
+            "Tests/SolutionTests/SolutionTests.swift",
+        ]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Swift package: Package.swift with an executable target App and a library target AppCore, sources in Sources/App/ and Sources/AppCore/, XCTest tests of AppCore in Tests/AppCoreTests/. Build with \"swift build\", test with \"swift test\"."
+    }
+}
+
+pub struct KotlinToolchain;
+
+impl Toolchain for KotlinToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Kotlin
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        [
+            "build.gradle",
+            "src/main/kotlin/Solution.kt",
+            "src/test/kotlin/SolutionTest.kt",
+        ]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Gradle project: build.gradle with the Kotlin JVM and application plugins and JUnit 5, sources in src/main/kotlin/ with a main function, tests in src/test/kotlin/. Build with \"gradle build -x test\", test with \"gradle test\"."
+    }
+}
+
+pub struct PythonToolchain;
+
+impl Toolchain for PythonToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Python
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        ["requirements.txt", "solution.py", "test.py"]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Python package: requirements.txt, the package app/ with __init__.py, __main__.py and one module per concern, unittest tests in tests/ with __init__.py. Build with \"python -m compileall -q app tests\", test with \"python -m unittest discover -s tests\"."
+    }
+}
+
+pub struct JavaScriptToolchain;
+
+impl Toolchain for JavaScriptToolchain {
+    fn lang(&self) -> Lang {
+        Lang::JavaScript
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        ["package.json", "src/solution.js", "src/solution.test.js"]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Node.js project: package.json with jest and the \"start\" and \"test\" scripts, modules in src/ with src/index.js as the entry point, jest tests in src/*.test.js. Install with \"npm install\", build with \"node --check src/index.js\", test with \"npm test\"."
+    }
+}
+
+pub struct TypeScriptToolchain;
+
+impl Toolchain for TypeScriptToolchain {
+    fn lang(&self) -> Lang {
+        Lang::TypeScript
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        ["package.json", "src/solution.ts", "src/solution.test.ts"]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Node.js TypeScript project: package.json with typescript, jest and ts-jest, tsconfig.json, modules in src/ with src/index.ts as the entry point, jest tests in src/*.test.ts. Install with \"npm install\", build with \"npx tsc\", test with \"npx jest\"."
+    }
+
+    fn rewrite_params(
+        &self,
+        project: &Project,
+        build_output: &str,
+        test_output: &str,
+        question: &str,
+    ) -> Vec<String> {
+        vec![
+            project.file("package.json").to_string(),
+            project.file("tsconfig.json").to_string(),
+            project.file("src/solution.ts").to_string(),
+            project.file("src/solution.test.ts").to_string(),
+            project.build_command.clone(),
+            build_output.to_string(),
+            project.test_command.clone(),
+            test_output.to_string(),
+            question.to_string(),
+        ]
+    }
+}
+
+pub struct PhpToolchain;
+
+impl Toolchain for PhpToolchain {
+    fn lang(&self) -> Lang {
+        Lang::Php
+    }
+
+    fn function_files(&self) -> [&'static str; 3] {
+        [
+            "composer.json",
+            "src/Solution.php",
+            "tests/SolutionTest.php",
+        ]
+    }
+
+    fn application_hint(&self) -> &'static str {
+        "a Composer project: composer.json with PSR-4 autoloading of App\\\\ from src/ and PHPUnit, classes in src/, the entry point bin/app.php, tests in tests/. Install with \"composer install\", test with \"vendor/bin/phpunit tests\"."
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm_prompt::Prompt;
+
+    // Only the build may be left out, the tests may not
+    #[test]
+    fn test_empty_commands() {
+        let toolchain = toolchain(&Lang::Python).unwrap();
+        let project = Project::default();
+        let sandbox = Path::new(SANDBOX_DIR);
+        let mut cache = Cache::default();
+        assert_eq!(
+            toolchain.build(&project, sandbox, &mut cache),
+            Ok((true, String::new()))
+        );
+        let (passed, output) = toolchain.test(&project, sandbox, &mut cache).unwrap();
+        assert!(!passed);
+        assert_eq!(output, NO_TEST_COMMAND);
+    }
+
+    // Every placeholder of the rewrite prompt gets a parameter and none is left over
+    #[test]
+    fn test_rewrite_params_match_prompts() {
+        let project = Project::default();
+        for lang in GENERATE_LANGS.iter() {
+            let toolchain = toolchain(lang).unwrap();
+            assert_eq!(toolchain.lang(), *lang);
+            let prompt = Prompt::new(&format!("prompt/{}.txt", lang));
+            // create() panics on a placeholder without parameter, and the question
+            // is the last parameter so a surplus one would leave it out
+            let params = toolchain.rewrite_params(&project, "", "", "the question");
+            assert!(
+                prompt.create("rewrite", &params).contains("the question"),
+                "{}",
+                lang
+            );
+        }
+    }
+}
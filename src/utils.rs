@@ -8,3 +8,19 @@ pub fn remove_comments(text: &str) -> String {
         .trim()
         .to_string()
 }
+
+// Rough token count for prompt budgets, about 4 characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+// FNV-1a, stable across runs and Rust versions unlike DefaultHasher, so it can
+// be persisted to detect changed files
+pub fn content_hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -1,5 +1,11 @@
+use bincode::{Decode, Encode};
 use std::collections::HashMap;
 
+// Below this size an exact scan is fast enough and always correct
+pub const ANN_MIN_VECTORS: usize = 2048;
+const IVF_TRAIN_ITERATIONS: usize = 8;
+const IVF_TRAIN_SAMPLES_PER_LIST: usize = 64;
+
 fn euclidean_distance(v1: &[f32], v2: &[f32]) -> f32 {
     v1.iter()
         .zip(v2.iter())
@@ -8,15 +14,196 @@ fn euclidean_distance(v1: &[f32], v2: &[f32]) -> f32 {
         .sqrt()
 }
 
-pub fn find_closest(target: &[f32], vectors: &HashMap<String, Vec<f32>>) -> Vec<(String, f32)> {
-    let mut distances: Vec<(String, f32)> = vectors
+// The k vectors closest to `target` among the ids `keep` accepts. The ANN index answers
+// when there is one and its probed lists hold k kept ids, the exact scan otherwise.
+pub fn find_closest(
+    target: &[f32],
+    vectors: &HashMap<String, Vec<f32>>,
+    k: usize,
+    ann: Option<&IvfIndex>,
+    keep: &dyn Fn(&str) -> bool,
+) -> Vec<(String, f32)> {
+    if let Some(ann) = ann {
+        let found = ann.search(target, vectors, k, ann.default_nprobe(), keep);
+        if found.len() == k {
+            return found;
+        }
+    }
+    find_closest_k(target, vectors, k, keep)
+}
+
+// Exact search over the ids `keep` accepts, it only sorts the k best results
+// instead of all of them
+fn find_closest_k(
+    target: &[f32],
+    vectors: &HashMap<String, Vec<f32>>,
+    k: usize,
+    keep: &dyn Fn(&str) -> bool,
+) -> Vec<(String, f32)> {
+    let distances = vectors
         .iter()
-        .map(|(k, v)| (k.clone(), euclidean_distance(target, v)))
+        .filter(|(id, _)| keep(id))
+        .map(|(id, v)| (id.as_str(), euclidean_distance(target, v)))
         .collect();
+    take_k_smallest(distances, k)
+}
 
-    distances.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-
+fn take_k_smallest(mut distances: Vec<(&str, f32)>, k: usize) -> Vec<(String, f32)> {
+    if k == 0 {
+        return vec![];
+    }
+    if distances.len() > k {
+        distances.select_nth_unstable_by(k - 1, |a, b| a.1.total_cmp(&b.1));
+        distances.truncate(k);
+    }
+    distances.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(b.0)));
     distances
+        .into_iter()
+        .map(|(id, d)| (id.to_string(), d))
+        .collect()
+}
+
+// Reciprocal rank fusion: merges rankings whose scores are not comparable
+// (distances vs BM25) by using only the position of each id in every ranking.
+pub fn reciprocal_rank_fusion(rankings: &[Vec<(String, f32)>], k: usize) -> Vec<(String, f32)> {
+    const RRF_K: f32 = 60.0;
+    let mut scores: HashMap<&str, f32> = HashMap::new();
+    for ranking in rankings {
+        for (rank, (id, _)) in ranking.iter().enumerate() {
+            *scores.entry(id.as_str()).or_insert(0.0) += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    let mut fused: Vec<(String, f32)> = scores
+        .into_iter()
+        .map(|(id, score)| (id.to_string(), score))
+        .collect();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    fused.truncate(k);
+    fused
+}
+
+// Inverted file index: vectors are bucketed by their nearest k-means centroid and
+// a query only scans the buckets of the `nprobe` centroids closest to it.
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct IvfIndex {
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<String>>,
+    trained_on: usize,
+}
+
+impl IvfIndex {
+    // None without vectors to train the centroids on
+    pub fn build(vectors: &HashMap<String, Vec<f32>>) -> Option<IvfIndex> {
+        if vectors.is_empty() {
+            return None;
+        }
+        let mut ids: Vec<&String> = vectors.keys().collect();
+        ids.sort();
+        let nlist = ((ids.len() as f64).sqrt() as usize).max(1);
+
+        let sample_size = (nlist * IVF_TRAIN_SAMPLES_PER_LIST).min(ids.len());
+        let step = (ids.len() / sample_size.max(1)).max(1);
+        let sample: Vec<&[f32]> = ids
+            .iter()
+            .step_by(step)
+            .take(sample_size)
+            .map(|id| vectors[*id].as_slice())
+            .collect();
+
+        let mut centroids: Vec<Vec<f32>> = (0..nlist)
+            .map(|i| sample[i * sample.len() / nlist].to_vec())
+            .collect();
+        let dim = centroids[0].len();
+        for _ in 0..IVF_TRAIN_ITERATIONS {
+            let mut sums = vec![vec![0f32; dim]; nlist];
+            let mut counts = vec![0usize; nlist];
+            for v in &sample {
+                let c = nearest_centroid(&centroids, v);
+                for (s, x) in sums[c].iter_mut().zip(v.iter()) {
+                    *s += x;
+                }
+                counts[c] += 1;
+            }
+            // Empty clusters keep their previous centroid
+            for (c, sum) in sums.into_iter().enumerate() {
+                if counts[c] > 0 {
+                    centroids[c] = sum.into_iter().map(|s| s / counts[c] as f32).collect();
+                }
+            }
+        }
+
+        let mut lists = vec![Vec::new(); nlist];
+        for id in ids {
+            lists[nearest_centroid(&centroids, &vectors[id])].push(id.clone());
+        }
+        Some(IvfIndex {
+            centroids,
+            lists,
+            trained_on: vectors.len(),
+        })
+    }
+
+    // Centroids are not retrained on insert, so callers should rebuild once the
+    // data has grown well past what the index was trained on.
+    pub fn needs_retrain(&self, size: usize) -> bool {
+        size > self.trained_on * 2 || size * 2 < self.trained_on
+    }
+
+    // The id must not be in the index yet, use `remove` first when replacing a vector
+    pub fn insert(&mut self, id: &str, vector: &[f32]) {
+        let c = nearest_centroid(&self.centroids, vector);
+        self.lists[c].push(id.to_string());
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        for list in self.lists.iter_mut() {
+            list.retain(|x| x != id);
+        }
+    }
+
+    pub fn default_nprobe(&self) -> usize {
+        (self.centroids.len() / 10).max(8).min(self.centroids.len())
+    }
+
+    pub fn search(
+        &self,
+        target: &[f32],
+        vectors: &HashMap<String, Vec<f32>>,
+        k: usize,
+        nprobe: usize,
+        keep: &dyn Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        let mut probes: Vec<(usize, f32)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, euclidean_distance(target, c)))
+            .collect();
+        probes.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let distances = probes
+            .iter()
+            .take(nprobe)
+            .flat_map(|(c, _)| self.lists[*c].iter())
+            .filter(|id| keep(id))
+            .filter_map(|id| {
+                vectors
+                    .get(id)
+                    .map(|v| (id.as_str(), euclidean_distance(target, v)))
+            })
+            .collect();
+        take_k_smallest(distances, k)
+    }
+}
+
+fn nearest_centroid(centroids: &[Vec<f32>], v: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i, euclidean_distance(v, c)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+        .unwrap_or(0)
 }
 
 pub fn _find_most_similar(
@@ -45,3 +232,110 @@ fn _cosine_similarity(v1: &[f32], v2: &[f32]) -> f32 {
 
     dot_product / (magnitude_v1 * magnitude_v2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small deterministic LCG so the test does not depend on a rand crate
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next_f32(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 40) as f32 / (1u64 << 24) as f32) * 2.0 - 1.0
+        }
+    }
+
+    // Embeddings of real code cluster by topic, so the fixture is a mixture of blobs
+    fn clustered_vectors(n: usize, dim: usize, clusters: usize) -> HashMap<String, Vec<f32>> {
+        let mut rng = Lcg(42);
+        let centers: Vec<Vec<f32>> = (0..clusters)
+            .map(|_| (0..dim).map(|_| rng.next_f32() * 4.0).collect())
+            .collect();
+        (0..n)
+            .map(|i| {
+                let center = &centers[i % clusters];
+                let v = center.iter().map(|c| c + rng.next_f32()).collect();
+                (format!("file_{}.rs", i), v)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_closest_k_matches_full_sort() {
+        let vectors = clustered_vectors(500, 16, 10);
+        let target = vectors["file_7.rs"].clone();
+        let mut full: Vec<f32> = vectors
+            .values()
+            .map(|v| euclidean_distance(&target, v))
+            .collect();
+        full.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let top = find_closest_k(&target, &vectors, 5, &|_| true);
+        assert_eq!(top.len(), 5);
+        assert_eq!(top[0].0, "file_7.rs");
+        for (a, b) in top.iter().zip(full.iter()) {
+            assert_eq!(a.1, *b);
+        }
+    }
+
+    #[test]
+    fn test_ivf_recall_against_brute_force() {
+        let vectors = clustered_vectors(4000, 32, 40);
+        let index = IvfIndex::build(&vectors).unwrap();
+        let mut rng = Lcg(7);
+        let k = 10;
+        let queries = 50;
+        let mut hits = 0;
+        for q in 0..queries {
+            let base = &vectors[&format!("file_{}.rs", q * 13)];
+            let target: Vec<f32> = base.iter().map(|x| x + rng.next_f32() * 0.5).collect();
+            let exact = find_closest_k(&target, &vectors, k, &|_| true);
+            let approx = index.search(&target, &vectors, k, index.default_nprobe(), &|_| true);
+            hits += approx
+                .iter()
+                .filter(|(id, _)| exact.iter().any(|(e, _)| e == id))
+                .count();
+        }
+        let recall = hits as f32 / (queries * k) as f32;
+        assert!(recall >= 0.9, "recall@{} is {}", k, recall);
+
+        assert!(IvfIndex::build(&HashMap::new()).is_none());
+        let found = find_closest(&vectors["file_1.rs"], &vectors, k, Some(&index), &|id| {
+            id == "file_1.rs"
+        });
+        assert_eq!(found.len(), 1);
+    }
+
+    #[test]
+    fn test_reciprocal_rank_fusion() {
+        let semantic = vec![
+            ("a.rs".to_string(), 0.1),
+            ("b.rs".to_string(), 0.2),
+            ("c.rs".to_string(), 0.3),
+        ];
+        let lexical = vec![("b.rs".to_string(), 12.0), ("c.rs".to_string(), 3.0)];
+        let fused = reciprocal_rank_fusion(&[semantic, lexical], 2);
+        assert_eq!(fused.len(), 2);
+        assert_eq!(fused[0].0, "b.rs");
+        assert_eq!(fused[1].0, "c.rs");
+    }
+
+    #[test]
+    fn test_ivf_insert_and_remove() {
+        let mut vectors = clustered_vectors(300, 8, 5);
+        let mut index = IvfIndex::build(&vectors).unwrap();
+        let v = vectors.remove("file_3.rs").unwrap();
+        index.remove("file_3.rs");
+        let res = index.search(&v, &vectors, 3, index.default_nprobe(), &|_| true);
+        assert!(res.iter().all(|(id, _)| id != "file_3.rs"));
+
+        vectors.insert("new.rs".to_string(), v.clone());
+        index.insert("new.rs", &v);
+        let res = index.search(&v, &vectors, 1, index.default_nprobe(), &|_| true);
+        assert_eq!(res[0].0, "new.rs");
+    }
+}